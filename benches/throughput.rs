@@ -0,0 +1,184 @@
+//! Proxy throughput against a real Redis: a single multiplexed connection on a
+//! current-thread runtime (the original setup) versus a connection pool on a
+//! multi-threaded runtime.
+//!
+//! ```text
+//! REDIS_URL=redis://127.0.0.1:6379 cargo bench --bench throughput
+//! ```
+//!
+//! `BENCH_CLIENTS` (default 64) keep-alive HTTP clients send `GET` commands
+//! for `BENCH_SECONDS` (default 5) per case.
+
+use serverless_redis::commands::CommandTable;
+use serverless_redis::config::Config;
+use serverless_redis::create_app;
+use serverless_redis::hub::Hub;
+use serverless_redis::models::AppState;
+use serverless_redis::policy::AccessControl;
+use serverless_redis::shutdown::Shutdown;
+use serverless_redis::upstream::Upstream;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn runtime(worker_threads: usize) -> tokio::runtime::Runtime {
+    let mut builder = if worker_threads == 1 {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    if worker_threads > 1 {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all().build().expect("runtime")
+}
+
+/// Serve the proxy on its own runtime and thread until `shutdown` fires
+fn start_server(
+    url: &str,
+    worker_threads: usize,
+    pool_size: usize,
+    shutdown: Shutdown,
+) -> (SocketAddr, std::thread::JoinHandle<()>) {
+    let mut config = Config::default();
+    config.redis.url = url.to_string();
+    config.redis.pool_size = pool_size;
+    config.features.pubsub = false;
+    let (tx, rx) = std::sync::mpsc::channel();
+    let handle = std::thread::spawn(move || {
+        runtime(worker_threads).block_on(async move {
+            let conn = Upstream::new(
+                config.redis.url.clone(),
+                config.timeouts.connect(),
+                pool_size,
+            );
+            conn.spawn();
+            tokio::time::timeout(Duration::from_secs(5), conn.wait_connected())
+                .await
+                .expect("Redis reachable at REDIS_URL");
+            let hub = Hub::new(config.redis.url.clone(), config.timeouts.connect(), 1, 1);
+            let state = AppState {
+                conn: conn.clone(),
+                redis_url: config.redis.url.clone(),
+                commands: Arc::new(CommandTable::default()),
+                replicas: None,
+                access: Arc::new(AccessControl::new(&config)),
+                config: Arc::new(config),
+                shutdown: shutdown.clone(),
+                hub,
+            };
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            axum::serve(listener, create_app(state))
+                .with_graceful_shutdown(async move { shutdown.wait().await })
+                .await
+                .unwrap();
+            conn.close();
+        });
+    });
+    (rx.recv().expect("server started"), handle)
+}
+
+/// One keep-alive client sending requests until `deadline`; returns how many
+/// completed
+async fn client(addr: SocketAddr, deadline: Instant) -> u64 {
+    let body = br#"["GET","bench:key"]"#;
+    let request = [
+        format!(
+            "POST / HTTP/1.1\r\nHost: bench\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .as_bytes(),
+        body,
+    ]
+    .concat();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut buf = Vec::with_capacity(4096);
+    let mut done = 0;
+    while Instant::now() < deadline {
+        stream.write_all(&request).await.unwrap();
+        read_response(&mut stream, &mut buf).await;
+        done += 1;
+    }
+    done
+}
+
+/// Read one HTTP/1.1 response with a `Content-Length` body
+async fn read_response(stream: &mut TcpStream, buf: &mut Vec<u8>) {
+    buf.clear();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]).to_ascii_lowercase();
+            assert!(
+                head.starts_with("http/1.1 200"),
+                "unexpected response: {}",
+                head
+            );
+            let len: usize = head
+                .lines()
+                .find_map(|l| l.strip_prefix("content-length:"))
+                .and_then(|v| v.trim().parse().ok())
+                .expect("content-length");
+            if buf.len() >= end + 4 + len {
+                return;
+            }
+        }
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "server closed the connection");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+fn run_case(url: &str, worker_threads: usize, pool_size: usize, clients: usize, secs: u64) -> f64 {
+    let shutdown = Shutdown::default();
+    let (addr, server) = start_server(url, worker_threads, pool_size, shutdown.clone());
+    let load = runtime(0);
+    let total: u64 = load.block_on(async {
+        let deadline = Instant::now() + Duration::from_secs(secs);
+        let tasks: Vec<_> = (0..clients)
+            .map(|_| tokio::spawn(client(addr, deadline)))
+            .collect();
+        let mut total = 0;
+        for t in tasks {
+            total += t.await.unwrap();
+        }
+        total
+    });
+    shutdown.trigger();
+    server.join().unwrap();
+    total as f64 / secs as f64
+}
+
+fn main() {
+    let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into());
+    let clients = env_or("BENCH_CLIENTS", 64);
+    let secs = env_or("BENCH_SECONDS", 5);
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    println!("{} clients, {}s per case, {} cores", clients, secs, cores);
+    let baseline = run_case(&url, 1, 1, clients, secs);
+    println!(
+        "{:<40} {:>10.0} req/s",
+        "current_thread, 1 connection", baseline
+    );
+    for pool_size in [1, 4, 8] {
+        let rps = run_case(&url, cores, pool_size, clients, secs);
+        println!(
+            "{:<40} {:>10.0} req/s  ({:.2}x)",
+            format!("{} threads, {} connection(s)", cores, pool_size),
+            rps,
+            rps / baseline
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::metrics::METRICS;
+use crate::upstream::{Upstream, INITIAL_BACKOFF, MAX_BACKOFF};
+use redis::{aio::ConnectionManager, Value};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// What the proxy needs to know about one command
+#[derive(Clone, Copy, Debug, Default)]
+struct CommandSpec {
+    read_only: bool,
+    /// Key positions can't be derived from first/last/step (EVAL, XREAD, ...)
+    movable_keys: bool,
+    first_key: i64,
+    last_key: i64,
+    step: i64,
+}
+
+/// Command metadata reported by the primary via `COMMAND`
+#[derive(Default)]
+pub struct CommandTable {
+    specs: RwLock<HashMap<String, CommandSpec>>,
+}
+
+impl CommandTable {
+    /// Load the command table from Redis, replacing whatever was cached before.
+    /// `COMMAND INFO` without names only lists every command from Redis 7.0,
+    /// so this uses plain `COMMAND`.
+    pub async fn load(&self, conn: &mut ConnectionManager) -> anyhow::Result<usize> {
+        let v: Value = redis::cmd("COMMAND").query_async(conn).await?;
+        let mut specs = HashMap::new();
+        if let Value::Array(entries) = v {
+            for entry in entries {
+                collect_specs(entry, &mut specs);
+            }
+        }
+        let count = specs.len();
+        if count == 0 {
+            anyhow::bail!("COMMAND returned no commands");
+        }
+        // Subcommands are labelled by their container
+        let names = specs.keys().filter(|n| !n.contains('|')).cloned().collect();
+        METRICS.set_command_names(names);
+        *self.specs.write().unwrap() = specs;
+        Ok(count)
+    }
+
+    /// A table of `COMMAND` reply entries, without asking Redis
+    #[cfg(test)]
+    pub(crate) fn from_entries(entries: Vec<Value>) -> Self {
+        let mut specs = HashMap::new();
+        for entry in entries {
+            collect_specs(entry, &mut specs);
+        }
+        CommandTable {
+            specs: RwLock::new(specs),
+        }
+    }
+
+    /// Load the table once the primary is reachable, retrying with backoff
+    /// until it succeeds. Until then every command counts as an unknown
+    /// write, so read-only tokens and replicas would be useless.
+    pub async fn load_with_retry(&self, upstream: &Upstream) {
+        let mut delay = INITIAL_BACKOFF;
+        loop {
+            let mut conn = upstream.wait_connected().await;
+            match self.load(&mut conn).await {
+                Ok(count) => {
+                    tracing::info!(commands = count, "loaded command table");
+                    return;
+                }
+                Err(e) => tracing::warn!(
+                    error = %e,
+                    retry_in_ms = delay.as_millis() as u64,
+                    "failed to load command table"
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Look up a command, trying its `container|subcommand` name first
+    fn spec(&self, cmd: &[String]) -> Option<CommandSpec> {
+        let name = cmd.first()?.to_ascii_lowercase();
+        let table = self.specs.read().unwrap();
+        if let Some(sub) = cmd.get(1) {
+            let full = format!("{}|{}", name, sub.to_ascii_lowercase());
+            if let Some(spec) = table.get(&full) {
+                return Some(*spec);
+            }
+        }
+        table.get(&name).copied()
+    }
+
+    /// Whether the command carries the `readonly` flag.
+    ///
+    /// Container commands (`OBJECT ENCODING`, `XINFO STREAM`, ...) are looked
+    /// up by their `container|subcommand` name first. Unknown commands are
+    /// treated as writes.
+    pub fn is_read_only(&self, cmd: &[String]) -> bool {
+        self.spec(cmd).is_some_and(|s| s.read_only)
+    }
+
+    /// The key arguments of `cmd`, or `None` when they can't be determined
+    /// statically: unknown commands and commands with movable keys.
+    pub fn keys<'a>(&self, cmd: &'a [String]) -> Option<Vec<&'a str>> {
+        let spec = self.spec(cmd)?;
+        if spec.movable_keys {
+            return None;
+        }
+        let mut keys = Vec::new();
+        if spec.first_key <= 0 || spec.step <= 0 {
+            return Some(keys);
+        }
+        let len = cmd.len() as i64;
+        let last = if spec.last_key < 0 {
+            len + spec.last_key
+        } else {
+            spec.last_key.min(len - 1)
+        };
+        let mut i = spec.first_key;
+        while i <= last {
+            keys.push(cmd[i as usize].as_str());
+            i += spec.step;
+        }
+        Some(keys)
+    }
+}
+
+fn flag_set(flags: &[Value], flag: &str) -> bool {
+    flags.iter().any(|f| match f {
+        Value::SimpleString(s) => s.eq_ignore_ascii_case(flag),
+        Value::BulkString(bs) => bs.eq_ignore_ascii_case(flag.as_bytes()),
+        _ => false,
+    })
+}
+
+fn int(v: Option<Value>) -> i64 {
+    match v {
+        Some(Value::Int(i)) => i,
+        _ => 0,
+    }
+}
+
+/// Parse one `COMMAND` entry (and its subcommands) into the spec map.
+///
+/// Entry layout: name, arity, flags, first key, last key, step, ACL
+/// categories, tips, key specs, subcommands.
+fn collect_specs(entry: Value, out: &mut HashMap<String, CommandSpec>) {
+    let Value::Array(fields) = entry else {
+        return;
+    };
+    let mut fields = fields.into_iter();
+    let name = match fields.next() {
+        Some(Value::BulkString(bs)) => String::from_utf8_lossy(&bs).to_ascii_lowercase(),
+        Some(Value::SimpleString(s)) => s.to_ascii_lowercase(),
+        _ => return,
+    };
+    let mut spec = CommandSpec::default();
+    if let Some(Value::Array(flags) | Value::Set(flags)) = fields.nth(1) {
+        spec.read_only = flag_set(&flags, "readonly");
+        spec.movable_keys = flag_set(&flags, "movablekeys");
+    }
+    spec.first_key = int(fields.next());
+    spec.last_key = int(fields.next());
+    spec.step = int(fields.next());
+    out.insert(name, spec);
+    if let Some(Value::Array(subcommands)) = fields.nth(3) {
+        for sub in subcommands {
+            collect_specs(sub, out);
+        }
+    }
+}
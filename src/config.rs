@@ -0,0 +1,1038 @@
+use crate::glob::Glob;
+use crate::logging::LogOptions;
+use crate::replicas::ReplicaStrategy;
+use clap::Parser;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Command-line flags. These override both the config file and the environment.
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about = "HTTP server for Redis with Upstash compatibility")]
+pub struct Cli {
+    /// Config file (TOML, or YAML with a .yaml/.yml extension). Also `SR_CONFIG`.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address to bind
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// Port to listen on
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Primary Redis URL
+    #[arg(long)]
+    pub redis_url: Option<String>,
+    /// Replica URL for read-only commands (repeatable)
+    #[arg(long = "replica")]
+    pub replicas: Vec<String>,
+    /// File holding the bearer token
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
+    /// Log filter, e.g. `info` or `debug`
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Validate the configuration and exit
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listener: ListenerConfig,
+    pub redis: RedisConfig,
+    pub auth: AuthConfig,
+    pub timeouts: TimeoutConfig,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+    pub tracing: TracingConfig,
+    pub features: FeaturesConfig,
+    pub policy: PolicyConfig,
+    pub reload: ReloadConfig,
+    pub runtime: RuntimeConfig,
+    pub compression: CompressionConfig,
+    pub cors: CorsConfig,
+    pub durable: DurableConfig,
+    pub keyspace: KeyspaceConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub bind: String,
+    pub port: u16,
+    /// Serve HTTPS (HTTP/1.1 and HTTP/2 via ALPN) instead of cleartext
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig {
+            bind: "0.0.0.0".into(),
+            port: 3000,
+            tls: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain
+    pub cert_file: PathBuf,
+    /// PEM private key
+    pub key_file: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+    pub replicas: Vec<String>,
+    pub replica_strategy: ReplicaStrategy,
+    pub replica_health_interval_ms: u64,
+    /// Multiplexed connections per endpoint (primary and each replica)
+    pub pool_size: usize,
+    /// Subscriber connections shared by every SSE and WebSocket subscriber
+    pub pubsub_connections: usize,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: "redis://127.0.0.1:6379".into(),
+            replicas: Vec::new(),
+            replica_strategy: ReplicaStrategy::RoundRobin,
+            replica_health_interval_ms: 5000,
+            pool_size: 4,
+            pubsub_connections: 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Single unnamed token, identified as `default` in logs
+    pub token: Option<String>,
+    pub tokens: Vec<TokenConfig>,
+    /// Put `/healthz` and `/readyz` behind the token too
+    pub probes_require_auth: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub name: String,
+    pub token: String,
+    /// Only commands flagged `readonly` (plus subscribing)
+    #[serde(default)]
+    pub read_only: bool,
+    /// When non-empty, the only commands this token may run
+    #[serde(default)]
+    pub allow_commands: Vec<String>,
+    #[serde(default)]
+    pub deny_commands: Vec<String>,
+    /// When non-empty, every key a command names must start with one of these
+    #[serde(default)]
+    pub key_prefixes: Vec<String>,
+    /// When non-empty, channel globs this token may subscribe to. Patterns
+    /// it psubscribes to must be no broader than one of these.
+    #[serde(default)]
+    pub subscribe_channels: Vec<String>,
+    /// When non-empty, channel globs this token may publish to
+    #[serde(default)]
+    pub publish_channels: Vec<String>,
+    /// Overrides `policy.rate_limit` for this token
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Token bucket: `per_second` commands on average, bursts of up to `burst`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub per_second: f64,
+    /// Defaults to one second's worth of commands
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn burst(&self) -> f64 {
+        match self.burst {
+            Some(b) => b as f64,
+            None => self.per_second.ceil().max(1.0),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// All configured tokens as `(name, secret)` pairs
+    pub fn all_tokens(&self) -> Vec<TokenConfig> {
+        let mut out = Vec::with_capacity(self.tokens.len() + 1);
+        if let Some(t) = self.token.as_ref().filter(|t| !t.is_empty()) {
+            out.push(TokenConfig {
+                name: "default".into(),
+                token: t.clone(),
+                ..Default::default()
+            });
+        }
+        out.extend(self.tokens.iter().cloned());
+        out
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub command_ms: u64,
+    pub pipeline_ms: u64,
+    pub connect_ms: u64,
+    pub ready_ms: u64,
+    /// How long to wait for in-flight requests after SIGTERM
+    pub shutdown_ms: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            command_ms: 3000,
+            pipeline_ms: 10000,
+            connect_ms: 5000,
+            ready_ms: 1000,
+            shutdown_ms: 30000,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn command(&self) -> Duration {
+        Duration::from_millis(self.command_ms)
+    }
+
+    pub fn pipeline(&self) -> Duration {
+        Duration::from_millis(self.pipeline_ms)
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(self.connect_ms)
+    }
+
+    pub fn ready(&self) -> Duration {
+        Duration::from_millis(self.ready_ms)
+    }
+
+    pub fn shutdown(&self) -> Duration {
+        Duration::from_millis(self.shutdown_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_body_bytes: usize,
+    /// 0 means unlimited
+    pub max_pipeline_commands: usize,
+    /// Messages buffered per channel for slow subscribers before they start
+    /// missing messages
+    pub subscriber_buffer: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_body_bytes: 2 * 1024 * 1024,
+            max_pipeline_commands: 0,
+            subscriber_buffer: 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: String,
+    pub sample_rate: f64,
+    pub command_args: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        let d = LogOptions::default();
+        LoggingConfig {
+            level: d.level,
+            format: if d.json { "json" } else { "text" }.into(),
+            sample_rate: d.sample_rate,
+            command_args: d.log_args,
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn options(&self) -> LogOptions {
+        LogOptions {
+            level: self.level.clone(),
+            json: !self.format.eq_ignore_ascii_case("text"),
+            sample_rate: self.sample_rate,
+            log_args: self.command_args,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            otlp_endpoint: None,
+            service_name: "serverless-redis".into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    /// Serve `/metrics`
+    pub metrics: bool,
+    /// Serve `/publish` and the SSE `/subscribe`, `/psubscribe` and
+    /// `/ssubscribe` routes, and allow subscribing over `/ws`
+    pub pubsub: bool,
+    /// Serve the `/ws` WebSocket endpoint
+    pub websocket: bool,
+    /// Serve the Redis Streams consumer group routes, `/xreadgroup` and friends
+    pub streams: bool,
+    /// Serve the SSE `/keyspace` and `/keyevent` notification routes
+    pub keyspace: bool,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig {
+            metrics: true,
+            pubsub: true,
+            websocket: true,
+            streams: true,
+            keyspace: true,
+        }
+    }
+}
+
+/// Cross-origin access for browser clients. Disabled while
+/// `allowed_origins` is empty.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Exact origins such as `https://app.example.com`, or `["*"]`
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable from browser code
+    pub exposed_headers: Vec<String>,
+    /// How long browsers may cache a preflight
+    pub max_age_secs: u64,
+    /// Send `Access-Control-Allow-Credentials` on the streaming routes (SSE and `/ws`)
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            allowed_headers: vec![
+                "authorization".into(),
+                "content-type".into(),
+                "content-encoding".into(),
+                "upstash-encoding".into(),
+                "x-request-id".into(),
+            ],
+            exposed_headers: vec!["x-request-id".into(), "retry-after".into()],
+            max_age_secs: 600,
+            allow_credentials: false,
+        }
+    }
+}
+
+/// Resumable SSE streams. When enabled, every `PUBLISH` that goes through
+/// the proxy is also appended to a capped Redis Stream per channel so that
+/// `?durable=true` subscribers can catch up with `Last-Event-ID`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DurableConfig {
+    pub enabled: bool,
+    /// Prefix of the per-channel stream keys
+    pub key_prefix: String,
+    /// Messages kept per channel, which bounds how far back a client can resume
+    pub max_len: usize,
+}
+
+impl Default for DurableConfig {
+    fn default() -> Self {
+        DurableConfig {
+            enabled: false,
+            key_prefix: "sr:durable:".into(),
+            max_len: 1000,
+        }
+    }
+}
+
+/// Keyspace notification streams
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyspaceConfig {
+    /// Turn on `notify-keyspace-events` with `CONFIG SET` at startup.
+    /// Managed Redis services often refuse this.
+    pub enable_notifications: bool,
+    /// Event classes to enable, in `notify-keyspace-events` syntax
+    pub classes: String,
+}
+
+impl Default for KeyspaceConfig {
+    fn default() -> Self {
+        KeyspaceConfig {
+            enable_notifications: false,
+            classes: "A".into(),
+        }
+    }
+}
+
+/// Response compression, negotiated from `Accept-Encoding`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// Responses smaller than this go out uncompressed
+    pub min_bytes: u16,
+    /// Any of `gzip`, `br` and `zstd`
+    pub algorithms: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            min_bytes: 1024,
+            algorithms: vec!["gzip".into(), "br".into(), "zstd".into()],
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn allows(&self, algorithm: &str) -> bool {
+        self.enabled
+            && self
+                .algorithms
+                .iter()
+                .any(|a| a.eq_ignore_ascii_case(algorithm))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Tokio worker threads; 0 means one per CPU core, 1 runs everything on
+    /// a single thread
+    pub worker_threads: usize,
+}
+
+/// Rules applied to every token. Reloadable, like `auth`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// Commands no token may run, e.g. `FLUSHALL` or `CONFIG`
+    pub deny_commands: Vec<String>,
+    /// Per-token rate limit for tokens without their own
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadConfig {
+    /// Reload when the config file or a token file changes
+    pub watch: bool,
+    pub interval_ms: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            watch: true,
+            interval_ms: 2000,
+        }
+    }
+}
+
+/// Read `NAME`, or the contents of the file named by `NAME_FILE` (Docker secrets)
+fn env_var(name: &str) -> anyhow::Result<Option<String>> {
+    if let Ok(path) = std::env::var(format!("{}_FILE", name)) {
+        let value = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("{}_FILE: cannot read {}: {}", name, path, e))?;
+        return Ok(Some(value.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    Ok(std::env::var(name).ok())
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    match env_var(name)? {
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{}: invalid value {:?}: {}", name, v, e)),
+        None => Ok(None),
+    }
+}
+
+fn split_list(v: &str) -> Vec<String> {
+    v.split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn parse_bool_env(name: &str) -> anyhow::Result<Option<bool>> {
+    match env_var(name)? {
+        Some(v) => match v.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" | "" => Ok(Some(false)),
+            _ => anyhow::bail!("{}: expected true or false, got {:?}", name, v),
+        },
+        None => Ok(None),
+    }
+}
+
+fn validate_rate_limit(name: &str, limit: &RateLimitConfig, errors: &mut Vec<String>) {
+    if !(limit.per_second > 0.0 && limit.per_second.is_finite()) {
+        errors.push(format!("{}.per_second must be positive", name));
+    }
+    if limit.burst == Some(0) {
+        errors.push(format!("{}.burst must be positive", name));
+    }
+}
+
+impl Config {
+    /// Parse a TOML or YAML config file, picked by extension
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read {}: {}", path.display(), e))?;
+        let yaml = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml" | "yml")
+        );
+        let config = if yaml {
+            serde_yaml::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+        } else {
+            toml::from_str(&text).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+        };
+        Ok(config)
+    }
+
+    /// Build the configuration: defaults, then the config file, then
+    /// environment variables, then command-line flags
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = match Config::path(cli)? {
+            Some(p) => Config::from_file(&p)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.apply_cli(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The config file in use, from `--config` or `SR_CONFIG`
+    pub fn path(cli: &Cli) -> anyhow::Result<Option<PathBuf>> {
+        match &cli.config {
+            Some(p) => Ok(Some(p.clone())),
+            None => Ok(env_var("SR_CONFIG")?.map(PathBuf::from)),
+        }
+    }
+
+    /// Files whose changes trigger a reload: the config file and token files
+    pub fn watched_files(cli: &Cli) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = Config::path(cli)?.into_iter().collect();
+        if let Ok(path) = std::env::var("SR_TOKEN_FILE") {
+            files.push(path.into());
+        }
+        files.extend(cli.token_file.clone());
+        Ok(files)
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        if let Some(v) = env_var("SR_BIND")? {
+            self.listener.bind = v;
+        }
+        if let Some(v) = parse_env("PORT")? {
+            self.listener.port = v;
+        }
+        match (env_var("SR_TLS_CERT_FILE")?, env_var("SR_TLS_KEY_FILE")?) {
+            (Some(cert), Some(key)) => {
+                self.listener.tls = Some(TlsConfig {
+                    cert_file: cert.into(),
+                    key_file: key.into(),
+                })
+            }
+            (None, None) => {}
+            _ => anyhow::bail!("SR_TLS_CERT_FILE and SR_TLS_KEY_FILE must be set together"),
+        }
+        if let Some(v) = env_var("REDIS_URL")? {
+            self.redis.url = v;
+        }
+        if let Some(v) = env_var("REDIS_REPLICA_URLS")? {
+            self.redis.replicas = split_list(&v);
+        }
+        if let Some(v) = parse_env("REDIS_POOL_SIZE")? {
+            self.redis.pool_size = v;
+        }
+        if let Some(v) = parse_env("REDIS_PUBSUB_CONNECTIONS")? {
+            self.redis.pubsub_connections = v;
+        }
+        if let Some(v) = parse_env("SR_WORKER_THREADS")? {
+            self.runtime.worker_threads = v;
+        }
+        if let Some(v) = parse_env("REDIS_REPLICA_STRATEGY")? {
+            self.redis.replica_strategy = v;
+        }
+        if let Some(v) = env_var("SR_TOKEN")? {
+            self.auth.token = Some(v);
+        }
+        if let Some(v) = env_var("SR_DENY_COMMANDS")? {
+            self.policy.deny_commands = split_list(&v);
+        }
+        if let Some(v) = parse_bool_env("SR_PROBES_REQUIRE_AUTH")? {
+            self.auth.probes_require_auth = v;
+        }
+        if let Some(v) = parse_env("SR_COMMAND_TIMEOUT_MS")? {
+            self.timeouts.command_ms = v;
+        }
+        if let Some(v) = parse_env("SR_PIPELINE_TIMEOUT_MS")? {
+            self.timeouts.pipeline_ms = v;
+        }
+        if let Some(v) = parse_env("SR_SHUTDOWN_TIMEOUT_MS")? {
+            self.timeouts.shutdown_ms = v;
+        }
+        if let Some(v) = parse_bool_env("SR_COMPRESSION")? {
+            self.compression.enabled = v;
+        }
+        if let Some(v) = parse_env("SR_COMPRESSION_MIN_BYTES")? {
+            self.compression.min_bytes = v;
+        }
+        if let Some(v) = env_var("SR_COMPRESSION_ALGORITHMS")? {
+            self.compression.algorithms = split_list(&v);
+        }
+        if let Some(v) = env_var("SR_CORS_ORIGINS")? {
+            self.cors.allowed_origins = split_list(&v);
+        }
+        if let Some(v) = parse_bool_env("SR_CORS_ALLOW_CREDENTIALS")? {
+            self.cors.allow_credentials = v;
+        }
+        if let Some(v) = parse_bool_env("SR_DURABLE")? {
+            self.durable.enabled = v;
+        }
+        if let Some(v) = parse_env("SR_DURABLE_MAX_LEN")? {
+            self.durable.max_len = v;
+        }
+        if let Some(v) = parse_bool_env("SR_KEYSPACE_ENABLE_NOTIFICATIONS")? {
+            self.keyspace.enable_notifications = v;
+        }
+        if let Some(v) = parse_env("SR_MAX_BODY_BYTES")? {
+            self.limits.max_body_bytes = v;
+        }
+        if let Some(v) = parse_env("SR_MAX_PIPELINE_COMMANDS")? {
+            self.limits.max_pipeline_commands = v;
+        }
+        if let Some(v) = parse_env("SR_SUBSCRIBER_BUFFER")? {
+            self.limits.subscriber_buffer = v;
+        }
+        if let Some(v) = env_var("SR_LOG_LEVEL")? {
+            self.logging.level = v;
+        }
+        if let Some(v) = env_var("SR_LOG_FORMAT")? {
+            self.logging.format = v;
+        }
+        if let Some(v) = parse_env("SR_LOG_SAMPLE_RATE")? {
+            self.logging.sample_rate = v;
+        }
+        if let Some(v) = parse_bool_env("SR_LOG_COMMAND_ARGS")? {
+            self.logging.command_args = v;
+        }
+        if let Some(v) = env_var("SR_OTLP_ENDPOINT")?.or(env_var("OTEL_EXPORTER_OTLP_ENDPOINT")?) {
+            self.tracing.otlp_endpoint = Some(v).filter(|v| !v.is_empty());
+        }
+        if let Some(v) = env_var("OTEL_SERVICE_NAME")? {
+            self.tracing.service_name = v;
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) -> anyhow::Result<()> {
+        if let Some(v) = &cli.bind {
+            self.listener.bind = v.clone();
+        }
+        if let Some(v) = cli.port {
+            self.listener.port = v;
+        }
+        if let Some(v) = &cli.redis_url {
+            self.redis.url = v.clone();
+        }
+        if !cli.replicas.is_empty() {
+            self.redis.replicas = cli.replicas.clone();
+        }
+        if let Some(path) = &cli.token_file {
+            let token = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("--token-file: cannot read {}: {}", path.display(), e)
+            })?;
+            self.auth.token = Some(token.trim_end_matches(['\r', '\n']).to_string());
+        }
+        if let Some(v) = &cli.log_level {
+            self.logging.level = v.clone();
+        }
+        Ok(())
+    }
+
+    /// Check every setting, reporting all problems at once
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if self.listen_addr().is_err() {
+            errors.push(format!(
+                "listener: invalid address {}:{}",
+                self.listener.bind, self.listener.port
+            ));
+        }
+        if let Some(tls) = &self.listener.tls {
+            if let Err(e) = crate::tls::load_config(&tls.cert_file, &tls.key_file) {
+                errors.push(format!("listener.tls: {:#}", e));
+            }
+        }
+        if let Err(e) = redis::Client::open(self.redis.url.as_str()) {
+            errors.push(format!("redis.url: {}", e));
+        }
+        for url in &self.redis.replicas {
+            if let Err(e) = redis::Client::open(url.as_str()) {
+                errors.push(format!("redis.replicas: {}: {}", url, e));
+            }
+        }
+        if self.redis.pool_size == 0 {
+            errors.push("redis.pool_size must be positive".into());
+        }
+        if self.redis.pubsub_connections == 0 {
+            errors.push("redis.pubsub_connections must be positive".into());
+        }
+        if self.limits.subscriber_buffer == 0 {
+            errors.push("limits.subscriber_buffer must be positive".into());
+        }
+        if self.durable.max_len == 0 {
+            errors.push("durable.max_len must be positive".into());
+        }
+        if self.durable.key_prefix.is_empty() {
+            errors.push("durable.key_prefix must not be empty".into());
+        }
+        if self.keyspace.classes.is_empty()
+            || !self
+                .keyspace
+                .classes
+                .chars()
+                .all(|c| "g$lshzxetdmnA".contains(c))
+        {
+            errors.push(format!(
+                "keyspace.classes: {:?} is not a set of notify-keyspace-events classes",
+                self.keyspace.classes
+            ));
+        }
+        if self.redis.replica_health_interval_ms == 0 {
+            errors.push("redis.replica_health_interval_ms must be positive".into());
+        }
+
+        let mut names = std::collections::HashSet::new();
+        for t in self.auth.all_tokens() {
+            if t.name.is_empty() {
+                errors.push("auth.tokens: name must not be empty".into());
+            }
+            if t.token.is_empty() {
+                errors.push(format!("auth.tokens: token {:?} is empty", t.name));
+            }
+            if !names.insert(t.name.clone()) {
+                errors.push(format!("auth.tokens: duplicate name {:?}", t.name));
+            }
+            for (field, globs) in [
+                ("subscribe_channels", &t.subscribe_channels),
+                ("publish_channels", &t.publish_channels),
+            ] {
+                for g in globs {
+                    if let Err(e) = Glob::check(g) {
+                        errors.push(format!("auth.tokens.{}.{}: {:?} {}", t.name, field, g, e));
+                    }
+                }
+            }
+            if let Some(limit) = &t.rate_limit {
+                validate_rate_limit(
+                    &format!("auth.tokens.{}.rate_limit", t.name),
+                    limit,
+                    &mut errors,
+                );
+            }
+        }
+        if let Some(limit) = &self.policy.rate_limit {
+            validate_rate_limit("policy.rate_limit", limit, &mut errors);
+        }
+        if self.reload.interval_ms == 0 {
+            errors.push("reload.interval_ms must be positive".into());
+        }
+
+        for (name, ms) in [
+            ("command_ms", self.timeouts.command_ms),
+            ("pipeline_ms", self.timeouts.pipeline_ms),
+            ("connect_ms", self.timeouts.connect_ms),
+            ("ready_ms", self.timeouts.ready_ms),
+            ("shutdown_ms", self.timeouts.shutdown_ms),
+        ] {
+            if ms == 0 {
+                errors.push(format!("timeouts.{} must be positive", name));
+            }
+        }
+        if self.limits.max_body_bytes == 0 {
+            errors.push("limits.max_body_bytes must be positive".into());
+        }
+
+        for a in &self.compression.algorithms {
+            if !matches!(a.to_ascii_lowercase().as_str(), "gzip" | "br" | "zstd") {
+                errors.push(format!(
+                    "compression.algorithms: unknown algorithm {:?} (expected gzip, br or zstd)",
+                    a
+                ));
+            }
+        }
+
+        if let Err(e) = crate::cors::validate(&self.cors) {
+            errors.push(e.to_string());
+        }
+
+        if !(0.0..=1.0).contains(&self.logging.sample_rate) {
+            errors.push("logging.sample_rate must be between 0 and 1".into());
+        }
+        if !matches!(
+            self.logging.format.to_ascii_lowercase().as_str(),
+            "json" | "text"
+        ) {
+            errors.push(format!(
+                "logging.format must be json or text, got {:?}",
+                self.logging.format
+            ));
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            errors.push(format!("logging.level: {}", e));
+        }
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                errors.push(format!(
+                    "tracing.otlp_endpoint must be an http(s) URL, got {:?}",
+                    endpoint
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid configuration:\n  - {}", errors.join("\n  - "))
+        }
+    }
+
+    /// Setting names that changed but only take effect after a restart
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        let sections = [
+            ("listener", self.listener != other.listener),
+            ("redis", self.redis != other.redis),
+            ("timeouts", self.timeouts != other.timeouts),
+            ("limits", self.limits != other.limits),
+            ("logging", self.logging != other.logging),
+            ("tracing", self.tracing != other.tracing),
+            ("features", self.features != other.features),
+            ("runtime", self.runtime != other.runtime),
+            ("compression", self.compression != other.compression),
+            ("cors", self.cors != other.cors),
+            ("durable", self.durable != other.durable),
+            ("keyspace", self.keyspace != other.keyspace),
+        ];
+        for (name, differs) in sections {
+            if differs {
+                changed.push(name);
+            }
+        }
+        if self.auth.probes_require_auth != other.auth.probes_require_auth {
+            changed.push("auth.probes_require_auth");
+        }
+        changed
+    }
+
+    pub fn listen_addr(&self) -> anyhow::Result<SocketAddr> {
+        let bind = &self.listener.bind;
+        let host = if bind.contains(':') && !bind.starts_with('[') {
+            format!("[{}]", bind)
+        } else {
+            bind.clone()
+        };
+        Ok(format!("{}:{}", host, self.listener.port).parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> anyhow::Result<Config> {
+        Ok(toml::from_str(text)?)
+    }
+
+    fn errors(text: &str) -> String {
+        let config = parse(text).unwrap();
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn port_out_of_range_is_rejected() {
+        assert!(parse("[listener]\nport = 70000").is_err());
+        assert!(parse("[listener]\nport = -1").is_err());
+        assert_eq!(
+            parse("[listener]\nport = 65535").unwrap().listener.port,
+            65535
+        );
+    }
+
+    #[test]
+    fn pool_sizes_must_be_positive() {
+        let e = errors("[redis]\npool_size = 0\npubsub_connections = 0");
+        assert!(e.contains("redis.pool_size must be positive"), "{}", e);
+        assert!(
+            e.contains("redis.pubsub_connections must be positive"),
+            "{}",
+            e
+        );
+        parse("[redis]\npool_size = 1\npubsub_connections = 1")
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn min_bytes_out_of_range_is_rejected() {
+        assert!(parse("[compression]\nmin_bytes = 65536").is_err());
+        assert!(parse("[compression]\nmin_bytes = -1").is_err());
+        parse("[compression]\nmin_bytes = 0")
+            .unwrap()
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let e = errors("[redis]\npool_size = 0\n[limits]\nmax_body_bytes = 0");
+        assert!(e.starts_with("invalid configuration:\n  - "), "{}", e);
+        assert_eq!(e.matches("\n  - ").count(), 2, "{}", e);
+    }
+
+    #[test]
+    fn channel_globs_are_checked() {
+        let e = errors(
+            r#"
+            [[auth.tokens]]
+            name = "news"
+            token = "t"
+            subscribe_channels = ["news.[ab"]
+            publish_channels = ["news.*\\"]
+            "#,
+        );
+        assert!(
+            e.contains(r#"auth.tokens.news.subscribe_channels: "news.[ab" has an unterminated ["#),
+            "{}",
+            e
+        );
+        assert!(
+            e.contains(r#"auth.tokens.news.publish_channels: "news.*\\" ends with an unescaped \"#),
+            "{}",
+            e
+        );
+        parse(
+            r#"
+            [[auth.tokens]]
+            name = "news"
+            token = "t"
+            subscribe_channels = ["news.*", "h?llo", "h[^e]llo", "a\\[b", "[a-z]\\]"]
+            "#,
+        )
+        .unwrap()
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn yaml_and_toml_load_the_same_config() {
+        let dir = std::env::temp_dir().join(format!("sr-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml_path = dir.join("config.toml");
+        let yaml_path = dir.join("config.yaml");
+        std::fs::write(
+            &toml_path,
+            r#"
+            [listener]
+            port = 8080
+
+            [redis]
+            pool_size = 4
+
+            [[auth.tokens]]
+            name = "news"
+            token = "t"
+            subscribe_channels = ["news.*"]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            &yaml_path,
+            r#"
+listener:
+  port: 8080
+redis:
+  pool_size: 4
+auth:
+  tokens:
+    - name: news
+      token: t
+      subscribe_channels: ["news.*"]
+"#,
+        )
+        .unwrap();
+        let from_toml = Config::from_file(&toml_path).unwrap();
+        let from_yaml = Config::from_file(&yaml_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for config in [&from_toml, &from_yaml] {
+            config.validate().unwrap();
+            assert_eq!(config.listener, from_toml.listener);
+            assert_eq!(config.redis, from_toml.redis);
+            assert_eq!(config.auth.tokens.len(), 1);
+            assert_eq!(config.auth.tokens[0].name, "news");
+            assert_eq!(config.auth.tokens[0].subscribe_channels, ["news.*"]);
+        }
+        assert_eq!(from_yaml.listener.port, 8080);
+        assert_eq!(from_yaml.redis.pool_size, 4);
+    }
+}
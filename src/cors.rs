@@ -0,0 +1,72 @@
+use crate::config::CorsConfig;
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+fn parse_list<T: std::str::FromStr>(field: &str, values: &[String]) -> anyhow::Result<Vec<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    values
+        .iter()
+        .map(|v| {
+            v.parse()
+                .map_err(|e| anyhow::anyhow!("cors.{}: invalid value {:?}: {}", field, v, e))
+        })
+        .collect()
+}
+
+/// Whether every origin is allowed
+fn any_origin(config: &CorsConfig) -> bool {
+    config.allowed_origins.iter().any(|o| o == "*")
+}
+
+/// Check the CORS settings, reporting the first problem
+pub fn validate(config: &CorsConfig) -> anyhow::Result<()> {
+    if any_origin(config) {
+        if config.allowed_origins.len() > 1 {
+            anyhow::bail!("cors.allowed_origins: \"*\" can't be combined with other origins");
+        }
+        if config.allow_credentials {
+            anyhow::bail!("cors.allow_credentials needs explicit allowed_origins, not \"*\"");
+        }
+    } else {
+        if let Some(o) = config
+            .allowed_origins
+            .iter()
+            .find(|o| !o.starts_with("http://") && !o.starts_with("https://"))
+        {
+            anyhow::bail!("cors.allowed_origins: {:?} is not an http(s) origin", o);
+        }
+        parse_list::<HeaderValue>("allowed_origins", &config.allowed_origins)?;
+    }
+    parse_list::<Method>("allowed_methods", &config.allowed_methods)?;
+    parse_list::<HeaderName>("allowed_headers", &config.allowed_headers)?;
+    parse_list::<HeaderName>("exposed_headers", &config.exposed_headers)?;
+    Ok(())
+}
+
+/// CORS layers for the command routes and for the streaming routes (the SSE
+/// streams and `/ws`), or `None` when no origins are configured. Only the
+/// stream layer sends `Access-Control-Allow-Credentials`, and only if enabled.
+pub fn layers(config: &CorsConfig) -> Option<(CorsLayer, CorsLayer)> {
+    if config.allowed_origins.is_empty() {
+        return None;
+    }
+    // Checked by `validate` at startup
+    let origin = if any_origin(config) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            parse_list::<HeaderValue>("allowed_origins", &config.allowed_origins).ok()?,
+        )
+    };
+    let base = CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(parse_list::<Method>("allowed_methods", &config.allowed_methods).ok()?)
+        .allow_headers(parse_list::<HeaderName>("allowed_headers", &config.allowed_headers).ok()?)
+        .expose_headers(parse_list::<HeaderName>("exposed_headers", &config.exposed_headers).ok()?)
+        .max_age(Duration::from_secs(config.max_age_secs));
+    let streams = base.clone().allow_credentials(config.allow_credentials);
+    Some((base, streams))
+}
@@ -0,0 +1,279 @@
+use crate::config::DurableConfig;
+use crate::hub::Delivery;
+use crate::pubsub::{encode_payload, PubSubMessage};
+use redis::aio::ConnectionManager;
+use redis::streams::StreamRangeReply;
+use redis::{ErrorKind, Script, ServerErrorKind};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Appends the message to the channel's stream, announces it together with
+/// its entry ID on a channel named after the stream key, then publishes it
+/// as usual so plain subscribers see no difference.
+const RECORD_SOURCE: &str = "\
+local id = redis.call('XADD', KEYS[1], 'MAXLEN', ARGV[3], '*', 'payload', ARGV[2])
+redis.call('PUBLISH', KEYS[1], id .. ' ' .. ARGV[2])
+return redis.call('PUBLISH', ARGV[1], ARGV[2])";
+
+/// `RECORD_SOURCE`, run with `EVALSHA` and loaded when Redis doesn't have it
+static RECORD_SCRIPT: LazyLock<Script> = LazyLock::new(|| Script::new(RECORD_SOURCE));
+
+/// The stream that records `channel`. Durable subscribers listen on a
+/// channel of the same name.
+pub fn stream_key(config: &DurableConfig, channel: &str) -> String {
+    format!("{}{}", config.key_prefix, channel)
+}
+
+/// Rewrite `PUBLISH channel message` into an `EVALSHA` of a script that
+/// also records the message. Any other command is returned unchanged.
+pub fn record(config: &DurableConfig, cmd: Vec<String>) -> Vec<String> {
+    if !config.enabled || cmd.len() != 3 || !cmd[0].eq_ignore_ascii_case("PUBLISH") {
+        return cmd;
+    }
+    vec![
+        "EVALSHA".into(),
+        RECORD_SCRIPT.get_hash().into(),
+        "1".into(),
+        stream_key(config, &cmd[1]),
+        cmd[1].clone(),
+        cmd[2].clone(),
+        config.max_len.to_string(),
+    ]
+}
+
+/// `PUBLISH channel payload` for a binary payload, recorded like
+/// [`record`] when durable mode is on
+pub fn publish_cmd(config: &DurableConfig, channel: &str, payload: &[u8]) -> redis::Cmd {
+    if !config.enabled {
+        let mut cmd = redis::cmd("PUBLISH");
+        cmd.arg(channel).arg(payload);
+        return cmd;
+    }
+    let mut cmd = redis::cmd("EVALSHA");
+    cmd.arg(RECORD_SCRIPT.get_hash())
+        .arg(1)
+        .arg(stream_key(config, channel))
+        .arg(channel)
+        .arg(payload)
+        .arg(config.max_len);
+    cmd
+}
+
+/// Whether `cmd` is a `PUBLISH` rewritten by [`record`]
+pub fn is_recorded(cmd: &[String]) -> bool {
+    cmd.len() > 1 && cmd[0].eq_ignore_ascii_case("EVALSHA") && cmd[1] == RECORD_SCRIPT.get_hash()
+}
+
+/// Whether a recorded publish failed because Redis doesn't have the script,
+/// after a restart, a failover or `SCRIPT FLUSH`. Nothing was published.
+pub fn is_noscript(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<redis::RedisError>()
+        .is_some_and(|e| e.kind() == ErrorKind::Server(ServerErrorKind::NoScript))
+}
+
+/// Load the recording script, after [`is_noscript`]
+pub async fn load_script(conn: &mut ConnectionManager, deadline: Duration) -> anyhow::Result<()> {
+    timeout(deadline, RECORD_SCRIPT.load_async(conn)).await??;
+    Ok(())
+}
+
+/// `SCRIPT LOAD` for the recording script as a command, to send ahead of
+/// recorded publishes in a pipeline. A `NOSCRIPT` there can't be retried,
+/// since the rest of the pipeline has already run.
+pub fn load_script_cmd() -> Vec<String> {
+    vec!["SCRIPT".into(), "LOAD".into(), RECORD_SOURCE.into()]
+}
+
+/// A Redis Stream entry ID, `<ms>-<seq>`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntryId {
+    ms: u64,
+    seq: u64,
+}
+
+impl FromStr for EntryId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid event ID {:?}, expected <ms>-<seq>", s);
+        let (ms, seq) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(EntryId {
+            ms: ms.parse().map_err(|_| invalid())?,
+            seq: seq.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for EntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// A recorded message, from a replay or from live delivery
+pub struct Entry {
+    pub channel: String,
+    pub id: EntryId,
+    pub payload: Vec<u8>,
+}
+
+impl Entry {
+    pub fn message(&self, base64: bool) -> PubSubMessage {
+        let (payload, base64) = encode_payload(&self.payload, base64);
+        PubSubMessage::Message {
+            channel: self.channel.clone(),
+            payload,
+            base64,
+        }
+    }
+}
+
+/// How far a durable subscriber has got in each channel's stream
+pub struct Cursor {
+    key_prefix: String,
+    positions: BTreeMap<String, EntryId>,
+}
+
+/// Why a `Last-Event-ID` can't resume a stream
+#[derive(Debug)]
+pub struct InvalidEventId(pub String);
+
+impl fmt::Display for InvalidEventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidEventId {}
+
+impl Cursor {
+    /// Position every channel after `last_event_id`, or at the current end
+    /// of its stream for a client that has not seen anything yet. Call this
+    /// before subscribing so that nothing published in between is lost.
+    ///
+    /// Event IDs hold one entry ID per channel, in channel name order, as
+    /// sent by [`Cursor::event_id`]; an ID for a different set of channels
+    /// fails with [`InvalidEventId`].
+    pub async fn start(
+        conn: &mut ConnectionManager,
+        config: &DurableConfig,
+        channels: &[String],
+        last_event_id: Option<&str>,
+        deadline: Duration,
+    ) -> anyhow::Result<Self> {
+        let positions = match last_event_id {
+            Some(event_id) => {
+                let names: BTreeMap<&String, ()> = channels.iter().map(|c| (c, ())).collect();
+                let ids = event_id
+                    .split(',')
+                    .map(|id| id.trim().parse::<EntryId>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(InvalidEventId)?;
+                if ids.len() != names.len() {
+                    return Err(InvalidEventId(format!(
+                        "Last-Event-ID resumes {} channels, but the stream follows {}.",
+                        ids.len(),
+                        names.len()
+                    ))
+                    .into());
+                }
+                names.into_keys().cloned().zip(ids).collect()
+            }
+            None => {
+                let mut pipe = redis::pipe();
+                for channel in channels {
+                    pipe.cmd("XREVRANGE")
+                        .arg(stream_key(config, channel))
+                        .arg("+")
+                        .arg("-")
+                        .arg("COUNT")
+                        .arg(1);
+                }
+                let tails: Vec<StreamRangeReply> =
+                    timeout(deadline, pipe.query_async(conn)).await??;
+                let mut positions = BTreeMap::new();
+                for (channel, tail) in channels.iter().zip(tails) {
+                    let id = match tail.ids.first() {
+                        Some(entry) => entry.id.parse().map_err(anyhow::Error::msg)?,
+                        None => EntryId::default(),
+                    };
+                    positions.insert(channel.clone(), id);
+                }
+                positions
+            }
+        };
+        Ok(Cursor {
+            key_prefix: config.key_prefix.clone(),
+            positions,
+        })
+    }
+
+    /// The SSE event ID for the current positions: each channel's entry ID,
+    /// in channel name order, separated by commas
+    pub fn event_id(&self) -> String {
+        let ids: Vec<String> = self.positions.values().map(EntryId::to_string).collect();
+        ids.join(",")
+    }
+
+    /// Entries recorded after the current positions, oldest first across
+    /// all channels. Each is [`Cursor::advance`]d past as it is sent.
+    pub async fn catch_up(
+        &self,
+        conn: &mut ConnectionManager,
+        deadline: Duration,
+    ) -> anyhow::Result<Vec<Entry>> {
+        let mut pipe = redis::pipe();
+        for (channel, pos) in &self.positions {
+            pipe.cmd("XRANGE")
+                .arg(format!("{}{}", self.key_prefix, channel))
+                .arg(format!("({}", pos))
+                .arg("+");
+        }
+        let replies: Vec<StreamRangeReply> = timeout(deadline, pipe.query_async(conn)).await??;
+        let mut entries = Vec::new();
+        for (channel, reply) in self.positions.keys().zip(replies) {
+            for entry in reply.ids {
+                entries.push(Entry {
+                    channel: channel.clone(),
+                    id: entry.id.parse().map_err(anyhow::Error::msg)?,
+                    payload: entry.get("payload").unwrap_or_default(),
+                });
+            }
+        }
+        entries.sort_by_key(|e| e.id);
+        Ok(entries)
+    }
+
+    /// Move past a replayed entry, returning the event ID to send with it
+    pub fn advance(&mut self, entry: &Entry) -> String {
+        if let Some(pos) = self.positions.get_mut(&entry.channel) {
+            *pos = (*pos).max(entry.id);
+        }
+        self.event_id()
+    }
+
+    /// The entry announced by a live delivery, or `None` if it was already
+    /// sent during a catch-up. Moves past it, like [`Cursor::advance`].
+    pub fn accept(&mut self, delivery: &Delivery) -> Option<Entry> {
+        let channel = delivery.channel.strip_prefix(&self.key_prefix)?;
+        let space = delivery.payload.iter().position(|&b| b == b' ')?;
+        let id: EntryId = std::str::from_utf8(&delivery.payload[..space])
+            .ok()?
+            .parse()
+            .ok()?;
+        let pos = self.positions.get_mut(channel)?;
+        if id <= *pos {
+            return None;
+        }
+        *pos = id;
+        Some(Entry {
+            channel: channel.to_string(),
+            id,
+            payload: delivery.payload[space + 1..].to_vec(),
+        })
+    }
+}
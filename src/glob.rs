@@ -0,0 +1,282 @@
+/// One element of a Redis glob pattern
+#[derive(Clone)]
+enum Token {
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// `[...]`, as the set of bytes it matches
+    Class(Box<[bool; 256]>),
+    Byte(u8),
+}
+
+/// A channel pattern in Redis `PSUBSCRIBE` syntax: `*`, `?`, `[...]` with
+/// ranges and `^`, and `\` escapes
+#[derive(Clone)]
+pub struct Glob(Vec<Token>);
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let p = pattern.as_bytes();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < p.len() {
+            match p[i] {
+                b'*' => {
+                    // Runs of `*` match the same as one
+                    if !matches!(tokens.last(), Some(Token::Any)) {
+                        tokens.push(Token::Any);
+                    }
+                }
+                b'?' => tokens.push(Token::One),
+                b'[' => {
+                    let (class, next) = parse_class(p, i + 1);
+                    tokens.push(Token::Class(class));
+                    i = next;
+                    continue;
+                }
+                b'\\' if i + 1 < p.len() => {
+                    i += 1;
+                    tokens.push(Token::Byte(p[i]));
+                }
+                c => tokens.push(Token::Byte(c)),
+            }
+            i += 1;
+        }
+        Glob(tokens)
+    }
+
+    /// Reject patterns Redis would read differently from what was likely
+    /// meant: an unterminated `[` class or a trailing `\`
+    pub fn check(pattern: &str) -> Result<(), &'static str> {
+        let p = pattern.as_bytes();
+        let mut i = 0;
+        let mut in_class = false;
+        while i < p.len() {
+            match p[i] {
+                b'\\' if i + 1 == p.len() => return Err("ends with an unescaped \\"),
+                b'\\' => i += 1,
+                b'[' if !in_class => in_class = true,
+                b']' if in_class => in_class = false,
+                _ => {}
+            }
+            i += 1;
+        }
+        if in_class {
+            return Err("has an unterminated [");
+        }
+        Ok(())
+    }
+
+    /// A pattern matching exactly `channel`
+    fn literal(channel: &str) -> Self {
+        Glob(channel.bytes().map(Token::Byte).collect())
+    }
+
+    /// Whether the channel name matches this pattern
+    pub fn matches(&self, channel: &str) -> bool {
+        self.covers(&Glob::literal(channel))
+    }
+
+    /// Whether every channel `other` matches is also matched by this
+    /// pattern. Conservative: some unusual pairs of classes are reported as
+    /// not covered even though they are.
+    pub fn covers(&self, other: &Glob) -> bool {
+        let (g, p) = (&self.0, &other.0);
+        // covered[i][j]: g[i..] covers p[j..]
+        let mut covered = vec![vec![false; p.len() + 1]; g.len() + 1];
+        covered[g.len()][p.len()] = true;
+        for i in (0..g.len()).rev() {
+            for j in (0..=p.len()).rev() {
+                covered[i][j] = match g[i] {
+                    // Either matches nothing more, or takes p[j] whatever it is
+                    Token::Any => covered[i + 1][j] || (j < p.len() && covered[i][j + 1]),
+                    _ => j < p.len() && covers_one(&g[i], &p[j]) && covered[i + 1][j + 1],
+                };
+            }
+        }
+        covered[0][0]
+    }
+
+    /// Whether the pattern matches some channel starting with `prefix`.
+    /// Errs towards `true` for classes that match nothing.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        let g = &self.0;
+        // Pattern positions reachable after the bytes consumed so far
+        let mut states = vec![false; g.len() + 1];
+        states[0] = true;
+        skip_stars(g, &mut states);
+        for b in prefix.bytes() {
+            let mut next = vec![false; g.len() + 1];
+            for i in (0..g.len()).filter(|&i| states[i]) {
+                match &g[i] {
+                    Token::Any => next[i] = true,
+                    Token::One => next[i + 1] = true,
+                    Token::Class(set) if set[b as usize] => next[i + 1] = true,
+                    Token::Byte(c) if *c == b => next[i + 1] = true,
+                    _ => {}
+                }
+            }
+            skip_stars(g, &mut next);
+            states = next;
+        }
+        states.iter().any(|&s| s)
+    }
+}
+
+/// Let every `*` in a set of pattern positions also match nothing
+fn skip_stars(g: &[Token], states: &mut [bool]) {
+    for i in 0..g.len() {
+        if states[i] && matches!(g[i], Token::Any) {
+            states[i + 1] = true;
+        }
+    }
+}
+
+/// Whether single-character token `g` matches everything `p` does
+fn covers_one(g: &Token, p: &Token) -> bool {
+    match (g, p) {
+        (_, Token::Any) => false,
+        (Token::One, _) => true,
+        (Token::Byte(a), Token::Byte(b)) => a == b,
+        (Token::Byte(a), Token::Class(set)) => (0..=255u8).all(|c| !set[c as usize] || c == *a),
+        (Token::Class(set), Token::Byte(b)) => set[*b as usize],
+        (Token::Class(set), Token::Class(other)) => (0..256).all(|c| !other[c] || set[c]),
+        (Token::Class(set), Token::One) => set.iter().all(|&m| m),
+        _ => false,
+    }
+}
+
+/// Parse a class starting after its `[`, returning the bytes it matches and
+/// the index after its `]`. An unterminated class runs to the end.
+fn parse_class(p: &[u8], mut i: usize) -> (Box<[bool; 256]>, usize) {
+    let mut set = Box::new([false; 256]);
+    let negate = p.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    while i < p.len() && p[i] != b']' {
+        if p[i] == b'\\' && i + 1 < p.len() {
+            i += 1;
+            set[p[i] as usize] = true;
+        } else if i + 2 < p.len() && p[i + 1] == b'-' && p[i + 2] != b']' {
+            let (lo, hi) = (p[i].min(p[i + 2]), p[i].max(p[i + 2]));
+            for c in lo..=hi {
+                set[c as usize] = true;
+            }
+            i += 2;
+        } else {
+            set[p[i] as usize] = true;
+        }
+        i += 1;
+    }
+    if negate {
+        for m in set.iter_mut() {
+            *m = !*m;
+        }
+    }
+    (set, (i + 1).min(p.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, channel: &str) -> bool {
+        Glob::new(pattern).matches(channel)
+    }
+
+    fn covers(pattern: &str, other: &str) -> bool {
+        Glob::new(pattern).covers(&Glob::new(other))
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(matches("news.*", "news."));
+        assert!(matches("news.*", "news.sport.today"));
+        assert!(matches("*", ""));
+        assert!(matches("a**b", "ab"));
+        assert!(matches("*.*", "a.b.c"));
+        assert!(!matches("news.*", "news"));
+        assert!(!matches("news.*", "other.news"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_byte() {
+        assert!(matches("h?llo", "hello"));
+        assert!(matches("h?llo", "hallo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(!matches("h?llo", "heello"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-c]llo", "hbllo"));
+        assert!(matches("h[c-a]llo", "hbllo"));
+        assert!(!matches("h[a-c]llo", "hdllo"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\]]", "]"));
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!matches("a\\?", "ab"));
+        assert!(matches("\\[x]", "[x]"));
+        assert!(!matches("\\[x]", "x"));
+    }
+
+    #[test]
+    fn covers_narrower_patterns() {
+        assert!(covers("news.*", "news.*"));
+        assert!(covers("news.*", "news.sport.*"));
+        assert!(covers("news.*", "news.?"));
+        assert!(covers("news.*", "news.[ab]"));
+        assert!(covers("*", "anything*"));
+        assert!(covers("h?llo", "h[ae]llo"));
+        assert!(covers("h[a-z]llo", "h[ae]llo"));
+        assert!(covers("h[^x]llo", "hello"));
+        assert!(covers("a\\*", "a\\*"));
+    }
+
+    #[test]
+    fn does_not_cover_broader_patterns() {
+        assert!(!covers("news.*", "*"));
+        assert!(!covers("news.*", "news*"));
+        assert!(!covers("news.?", "news.*"));
+        assert!(!covers("h[ae]llo", "h?llo"));
+        assert!(!covers("h[ae]llo", "h[a-z]llo"));
+        assert!(!covers("a\\*", "a*"));
+    }
+
+    #[test]
+    fn matches_prefix() {
+        let g = Glob::new("news.[ab]*");
+        assert!(g.matches_prefix(""));
+        assert!(g.matches_prefix("new"));
+        assert!(g.matches_prefix("news.a"));
+        assert!(g.matches_prefix("news.bxyz"));
+        assert!(!g.matches_prefix("news.c"));
+        assert!(!g.matches_prefix("other"));
+        assert!(Glob::new("*").matches_prefix("__keyspace@0__:"));
+        assert!(!Glob::new("app:*").matches_prefix("__keyspace@"));
+    }
+
+    #[test]
+    fn check() {
+        assert!(Glob::check("news.*").is_ok());
+        assert!(Glob::check("h[^a-c]llo").is_ok());
+        assert!(Glob::check("a\\[").is_ok());
+        assert!(Glob::check("[\\]]").is_ok());
+        assert!(Glob::check("news.[ab").is_err());
+        assert!(Glob::check("[\\]").is_err());
+        assert!(Glob::check("news\\").is_err());
+    }
+}
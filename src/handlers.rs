@@ -1,12 +1,157 @@
+use crate::durable;
+use crate::logging::RequestLog;
+use crate::metrics::{command_label, SseGuard, METRICS};
 use crate::models::{AppState, EnvResp};
-use crate::redis_client::{do_call, execute_pipeline};
+use crate::policy::{Denied, Principal};
+use crate::redis_client::{do_call, execute_pipeline, publish};
+use crate::replicas::is_connection_failure;
+use crate::upstream::{is_unavailable, UpstreamUnavailable};
 use crate::utils::write_resp;
-use axum::{extract::State, http::HeaderMap, response::Response, Json};
+use axum::{
+    extract::State,
+    http::{header::RETRY_AFTER, HeaderMap, HeaderValue},
+    response::Response,
+    Extension, Json,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Run a single command, sending read-only commands to a replica when one is healthy
+pub(crate) async fn routed_call(
+    state: &AppState,
+    cmd: Vec<String>,
+) -> anyhow::Result<serde_json::Value> {
+    let cmd = durable::record(&state.config.durable, cmd);
+    let timeouts = &state.config.timeouts;
+    if durable::is_recorded(&cmd) {
+        let mut conn = state.conn.connection()?;
+        return match do_call(&mut conn, cmd.clone(), timeouts.command()).await {
+            Err(e) if durable::is_noscript(&e) => {
+                durable::load_script(&mut conn, timeouts.command()).await?;
+                do_call(&mut conn, cmd, timeouts.command()).await
+            }
+            res => res,
+        };
+    }
+    if let Some(replicas) = &state.replicas {
+        if state.commands.is_read_only(&cmd) {
+            if let Some((idx, mut conn)) = replicas.pick() {
+                match do_call(&mut conn, cmd.clone(), timeouts.command()).await {
+                    Err(e) if is_connection_failure(&e) => replicas.mark_unhealthy(idx),
+                    res => return res,
+                }
+            }
+        }
+    }
+    do_call(&mut state.conn.connection()?, cmd, timeouts.command()).await
+}
+
+/// Run a pipeline, sending it to a replica only when every command is read-only
+async fn routed_pipeline(
+    state: &AppState,
+    cmds: Vec<Vec<String>>,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let timeouts = &state.config.timeouts;
+    if let Some(replicas) = &state.replicas {
+        if !cmds.is_empty() && cmds.iter().all(|c| state.commands.is_read_only(c)) {
+            if let Some((idx, mut conn)) = replicas.pick() {
+                match execute_pipeline(&mut conn, cmds.clone(), timeouts.pipeline()).await {
+                    Err(e) if is_connection_failure(&e) => replicas.mark_unhealthy(idx),
+                    res => return res,
+                }
+            }
+        }
+    }
+    execute_pipeline(&mut state.conn.connection()?, cmds, timeouts.pipeline()).await
+}
+
+/// `connection_error` when Redis could not be reached, `error` for anything else
+pub(crate) fn error_status(e: &anyhow::Error) -> String {
+    if e.is::<UpstreamUnavailable>() {
+        // Never reached `do_call`, so count it here
+        METRICS.redis_connection_errors.inc();
+    }
+    if is_unavailable(e) {
+        "connection_error".into()
+    } else {
+        "error".into()
+    }
+}
+
+/// Check `cmds` against the caller's permissions and rate limit, returning
+/// the rejection to send if any
+pub(crate) fn authorize(
+    state: &AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    cmds: &[Vec<String>],
+    enc: bool,
+) -> Option<Response> {
+    let denied = state.access.check(principal, &state.commands, cmds).err()?;
+    Some(refuse(log, denied, enc))
+}
+
+/// `authorize` for subscribing outside `/keyspace`, where tokens limited to
+/// key prefixes may not follow keyspace notifications
+fn authorize_subscribe(
+    state: &AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    cmds: &[Vec<String>],
+    enc: bool,
+) -> Option<Response> {
+    let denied = state
+        .access
+        .check_subscribe(principal, &state.commands, cmds)
+        .err()?;
+    Some(refuse(log, denied, enc))
+}
+
+/// The response to a request `denied` by policy
+fn refuse(log: &RequestLog, denied: Denied, enc: bool) -> Response {
+    log.set_error(denied.to_string());
+    let status = match denied {
+        Denied::RateLimited(_) => "rate_limited",
+        _ => "forbidden",
+    };
+    let mut resp = write_resp(
+        EnvResp {
+            status: status.into(),
+            result: None,
+            result_list: None,
+            error: Some(denied.to_string()),
+            message: None,
+        },
+        enc,
+    );
+    if let Denied::RateLimited(wait) = denied {
+        let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+        resp.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(secs));
+    }
+    resp
+}
 
 pub async fn post_root(
-    State(mut state): State<AppState>,
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
+) -> Response {
+    let start = Instant::now();
+    let command = command_label(body.get(0).and_then(|v| v.as_str()));
+    let resp = run_command(state, &principal, &log, headers, body).await;
+    METRICS.observe_request("/", &command, resp.status(), start.elapsed());
+    resp
+}
+
+async fn run_command(
+    state: AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
+    body: serde_json::Value,
 ) -> Response {
     let enc = headers
         .get("upstash-encoding")
@@ -53,8 +198,12 @@ pub async fn post_root(
         };
         cmd.push(arg);
     }
+    log.set_commands(std::slice::from_ref(&cmd));
+    if let Some(resp) = authorize(&state, principal, log, std::slice::from_ref(&cmd), enc) {
+        return resp;
+    }
 
-    match do_call(&mut state.conn, cmd).await {
+    match routed_call(&state, cmd).await {
         Ok(v) => write_resp(
             EnvResp {
                 status: "ok".into(),
@@ -65,23 +214,56 @@ pub async fn post_root(
             },
             enc,
         ),
-        Err(e) => write_resp(
-            EnvResp {
-                status: "error".into(),
-                result: None,
-                result_list: None,
-                error: Some(e.to_string()),
-                message: None,
-            },
-            enc,
-        ),
+        Err(e) => {
+            log.set_error(e.to_string());
+            write_resp(
+                EnvResp {
+                    status: error_status(&e),
+                    result: None,
+                    result_list: None,
+                    error: Some(e.to_string()),
+                    message: None,
+                },
+                enc,
+            )
+        }
     }
 }
 
 pub async fn post_pipeline(
-    State(mut state): State<AppState>,
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
     headers: HeaderMap,
     Json(body): Json<serde_json::Value>,
+) -> Response {
+    let start = Instant::now();
+    let resp = run_pipeline(state, &principal, &log, headers, body, true).await;
+    METRICS.observe_request("/pipeline", "pipeline", resp.status(), start.elapsed());
+    resp
+}
+
+pub async fn post_multi_exec(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    // Transactions always stay on the primary
+    let start = Instant::now();
+    let resp = run_pipeline(state, &principal, &log, headers, body, false).await;
+    METRICS.observe_request("/multi-exec", "multi-exec", resp.status(), start.elapsed());
+    resp
+}
+
+async fn run_pipeline(
+    state: AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
+    body: serde_json::Value,
+    allow_replicas: bool,
 ) -> Response {
     let enc = headers
         .get("upstash-encoding")
@@ -104,6 +286,23 @@ pub async fn post_pipeline(
     }
 
     let outer = outer.unwrap();
+    let max = state.config.limits.max_pipeline_commands;
+    if max > 0 && outer.len() > max {
+        return write_resp(
+            EnvResp {
+                status: "malformed_data".into(),
+                result: None,
+                result_list: None,
+                error: Some(format!(
+                    "Pipeline has {} commands; the limit is {}.",
+                    outer.len(),
+                    max
+                )),
+                message: None,
+            },
+            enc,
+        );
+    }
     let mut cmds = Vec::with_capacity(outer.len());
     for item in outer {
         let arr = item.as_array();
@@ -149,11 +348,37 @@ pub async fn post_pipeline(
         }
         cmds.push(cmd);
     }
+    METRICS.pipeline_size.observe(cmds.len() as f64);
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize(&state, principal, log, &cmds, enc) {
+        return resp;
+    }
+    let mut cmds: Vec<Vec<String>> = cmds
+        .into_iter()
+        .map(|cmd| durable::record(&state.config.durable, cmd))
+        .collect();
+    // Recorded publishes can't be retried after a NOSCRIPT once the rest of
+    // the pipeline has run, so load the script ahead of them
+    let preload = cmds.iter().any(|cmd| durable::is_recorded(cmd));
+    if preload {
+        cmds.insert(0, durable::load_script_cmd());
+    }
 
-    match execute_pipeline(&mut state.conn, cmds).await {
+    let res = if allow_replicas {
+        routed_pipeline(&state, cmds).await
+    } else {
+        match state.conn.connection() {
+            Ok(mut conn) => {
+                execute_pipeline(&mut conn, cmds, state.config.timeouts.pipeline()).await
+            }
+            Err(e) => Err(e),
+        }
+    };
+    match res {
         Ok(results) => {
             let out: Vec<serde_json::Value> = results
                 .into_iter()
+                .skip(preload as usize)
                 .map(|v| serde_json::json!({"status": "ok", "result": v}))
                 .collect();
             write_resp(
@@ -167,43 +392,224 @@ pub async fn post_pipeline(
                 enc,
             )
         }
-        Err(e) => write_resp(
+        Err(e) => {
+            log.set_error(e.to_string());
+            write_resp(
+                EnvResp {
+                    status: error_status(&e),
+                    result: None,
+                    result_list: None,
+                    error: Some(e.to_string()),
+                    message: None,
+                },
+                enc,
+            )
+        }
+    }
+}
+
+/// `POST /publish/{*channels}`: publish the raw request body, whatever its
+/// content type, to every channel in the path, with `SPUBLISH` if
+/// `?sharded=true`
+pub async fn post_publish(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    Path(channels): Path<String>,
+    params: Result<Query<PublishParams>, QueryRejection>,
+    body: Bytes,
+) -> Response {
+    let start = Instant::now();
+    let sharded = params.as_ref().is_ok_and(|Query(p)| p.sharded);
+    let enc = headers
+        .get("upstash-encoding")
+        .and_then(|v| v.to_str().ok())
+        == Some("base64");
+    let resp = match params {
+        Ok(Query(params)) => {
+            run_publish(state, &principal, &log, channels, params, body, enc).await
+        }
+        Err(e) => malformed(e.body_text()),
+    };
+    let command = if sharded { "spublish" } else { "publish" };
+    METRICS.observe_request("/publish", command, resp.status(), start.elapsed());
+    resp
+}
+
+async fn run_publish(
+    state: AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    channels: String,
+    params: PublishParams,
+    body: Bytes,
+    enc: bool,
+) -> Response {
+    let channel_list: Vec<String> = channels
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    if channel_list.is_empty() {
+        return malformed("No channels specified".into());
+    }
+
+    // Checked and logged as the equivalent PUBLISH commands, less the
+    // message: policy only looks at the channel
+    let name = if params.sharded {
+        "SPUBLISH"
+    } else {
+        "PUBLISH"
+    };
+    let cmds: Vec<Vec<String>> = channel_list
+        .iter()
+        .map(|c| vec![name.to_string(), c.clone()])
+        .collect();
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize(&state, principal, log, &cmds, enc) {
+        return resp;
+    }
+
+    // Shard channels aren't recorded for durable subscribers
+    let publishes: Vec<redis::Cmd> = channel_list
+        .iter()
+        .map(|c| {
+            if !params.sharded {
+                return durable::publish_cmd(&state.config.durable, c, &body);
+            }
+            let mut cmd = redis::cmd("SPUBLISH");
+            cmd.arg(c).arg(&body[..]);
+            cmd
+        })
+        .collect();
+    let deadline = state.config.timeouts.pipeline();
+    let res = match state.conn.connection() {
+        Ok(mut conn) => match publish(&mut conn, publishes.clone(), deadline).await {
+            // Every publish is the same script, so none of them ran
+            Err(e) if durable::is_noscript(&e) => {
+                match durable::load_script(&mut conn, deadline).await {
+                    Ok(()) => publish(&mut conn, publishes, deadline).await,
+                    Err(e) => Err(e),
+                }
+            }
+            res => res,
+        },
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(counts) => write_resp(
             EnvResp {
-                status: "error".into(),
-                result: None,
+                status: "ok".into(),
+                result: Some(serde_json::json!(counts)),
                 result_list: None,
-                error: Some(e.to_string()),
+                error: None,
                 message: None,
             },
             enc,
         ),
+        Err(e) => {
+            log.set_error(e.to_string());
+            write_resp(
+                EnvResp {
+                    status: error_status(&e),
+                    result: None,
+                    result_list: None,
+                    error: Some(e.to_string()),
+                    message: None,
+                },
+                enc,
+            )
+        }
     }
 }
 
-pub async fn post_multi_exec(
-    state: State<AppState>,
-    headers: HeaderMap,
-    body: Json<serde_json::Value>,
-) -> Response {
-    post_pipeline(state, headers, body).await
-}
-
+use crate::hub::{Signal, Topic};
 use crate::pubsub::{
-    create_pubsub_connection, format_sse_message, parse_redis_message, subscribe_to_channels,
-    psubscribe_to_patterns, PubSubMessage,
+    lagged_event, reconnect_event, reconnecting_event, resubscribed_event, sse_event,
+    PayloadEncoding, PubSubMessage, PublishParams, StreamParams, SubscribeBody,
 };
 use axum::{
-    extract::Path,
+    body::Bytes,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Path, Query,
+    },
     response::sse::{Event, KeepAlive, Sse},
 };
 use futures::stream::Stream;
 use std::convert::Infallible;
-use tokio_stream::StreamExt;
 
 pub async fn get_subscribe(
+    state: State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    channels: Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = subscribe_stream(state, &principal, &log, headers, channels, params).await;
+    observe_stream("/subscribe", "subscribe", &res, start);
+    res
+}
+
+/// The stream options requested in the query string
+fn stream_params(
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<StreamParams, String> {
+    match params {
+        Ok(Query(params)) => Ok(params),
+        Err(e) => Err(e.body_text()),
+    }
+}
+
+/// Whether stream payloads should be base64, as asked for with the
+/// `upstash-encoding` header or, for `EventSource` clients that can't set
+/// headers, `?encoding=base64`
+fn payload_base64(headers: &HeaderMap, params: &StreamParams) -> bool {
+    params.encoding == PayloadEncoding::Base64
+        || headers
+            .get("upstash-encoding")
+            .and_then(|v| v.to_str().ok())
+            == Some("base64")
+}
+
+pub(crate) fn malformed(error: String) -> Response {
+    write_resp(
+        EnvResp {
+            status: "malformed_data".into(),
+            result: None,
+            result_list: None,
+            error: Some(error),
+            message: None,
+        },
+        false,
+    )
+}
+
+pub(crate) fn observe_stream<T>(
+    route: &str,
+    command: &str,
+    res: &Result<T, Response>,
+    start: Instant,
+) {
+    let status = match res {
+        Ok(_) => axum::http::StatusCode::OK,
+        Err(resp) => resp.status(),
+    };
+    METRICS.observe_request(route, command, status, start.elapsed());
+}
+
+async fn subscribe_stream(
     State(state): State<AppState>,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
     Path(channels): Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let params = stream_params(params).map_err(malformed)?;
     // Parse channels from path - they come as a comma-separated or slash-separated string
     let channel_list: Vec<String> = channels
         .split('/')
@@ -224,68 +630,46 @@ pub async fn get_subscribe(
         ));
     }
 
-    // Create a dedicated Pub/Sub connection
-    let mut pubsub = match create_pubsub_connection(&state.redis_url).await {
-        Ok(ps) => ps,
-        Err(e) => {
-            return Err(write_resp(
-                EnvResp {
-                    status: "connection_error".into(),
-                    result: None,
-                    result_list: None,
-                    error: Some(format!("Failed to create pubsub connection: {}", e)),
-                    message: None,
-                },
-                false,
-            ));
-        }
-    };
-
-    // Subscribe to channels
-    match subscribe_to_channels(&mut pubsub, &channel_list).await {
-        Ok(_) => {},
-        Err(e) => {
-            return Err(write_resp(
-                EnvResp {
-                    status: "error".into(),
-                    result: None,
-                    result_list: None,
-                    error: Some(format!("Failed to subscribe: {}", e)),
-                    message: None,
-                },
-                false,
-            ));
-        }
-    };
-
-    // Create the SSE stream
-    let stream = async_stream::stream! {
-        // Send initial subscribe events - one per channel with incrementing count
-        for (idx, channel) in channel_list.iter().enumerate() {
-            let subscribe_msg = PubSubMessage::Subscribe { 
-                channel: channel.clone(), 
-                count: idx + 1 
-            };
-            yield Ok(Event::default().data(format_sse_message(&subscribe_msg)));
-        }
-
-        // Stream messages
-        let mut message_stream = pubsub.on_message();
-        while let Some(msg) = message_stream.next().await {
-            if let Some(parsed) = parse_redis_message(&msg) {
-                let sse_data = format_sse_message(&parsed);
-                yield Ok(Event::default().data(sse_data));
-            }
-        }
-    };
-
-    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+    topic_stream(
+        state,
+        principal,
+        log,
+        headers,
+        channel_list,
+        Vec::new(),
+        params,
+    )
+    .await
 }
 
 pub async fn get_psubscribe(
+    state: State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    patterns: Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = psubscribe_stream(state, &principal, &log, headers, patterns, params).await;
+    observe_stream("/psubscribe", "psubscribe", &res, start);
+    res
+}
+
+async fn psubscribe_stream(
     State(state): State<AppState>,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
     Path(patterns): Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let params = stream_params(params).map_err(malformed)?;
+    if params.durable {
+        return Err(malformed(
+            "Durable streams are only available on /subscribe.".into(),
+        ));
+    }
     // Parse patterns from path
     let pattern_list: Vec<String> = patterns
         .split('/')
@@ -306,57 +690,353 @@ pub async fn get_psubscribe(
         ));
     }
 
-    // Create a dedicated Pub/Sub connection
-    let mut pubsub = match create_pubsub_connection(&state.redis_url).await {
-        Ok(ps) => ps,
-        Err(e) => {
+    topic_stream(
+        state,
+        principal,
+        log,
+        headers,
+        Vec::new(),
+        pattern_list,
+        params,
+    )
+    .await
+}
+
+/// `GET /subscribe?channel=a&pattern=b.*`: channels and patterns in one
+/// stream, with names that may contain `/`
+pub async fn get_subscribe_topics(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    pairs: Result<Query<Vec<(String, String)>>, QueryRejection>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = async {
+        let params = stream_params(params).map_err(malformed)?;
+        let Query(pairs) = pairs.map_err(|e| malformed(e.body_text()))?;
+        let (mut channels, mut patterns) = (Vec::new(), Vec::new());
+        for (key, value) in pairs {
+            match key.as_str() {
+                "channel" => channels.push(value),
+                "pattern" => patterns.push(value),
+                _ => {}
+            }
+        }
+        topic_stream(state, &principal, &log, headers, channels, patterns, params).await
+    }
+    .await;
+    observe_stream("/subscribe", "subscribe", &res, start);
+    res
+}
+
+/// `POST /subscribe` with `{"channels": [...], "patterns": [...]}`
+pub async fn post_subscribe_topics(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    params: Result<Query<StreamParams>, QueryRejection>,
+    body: Result<Json<SubscribeBody>, JsonRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = async {
+        let params = stream_params(params).map_err(malformed)?;
+        let Json(body) = body.map_err(|e| malformed(e.body_text()))?;
+        topic_stream(
+            state,
+            &principal,
+            &log,
+            headers,
+            body.channels,
+            body.patterns,
+            params,
+        )
+        .await
+    }
+    .await;
+    observe_stream("/subscribe", "subscribe", &res, start);
+    res
+}
+
+/// Stream messages from `channel_list` and `pattern_list` on the shared
+/// subscriber connections. The stream opens with a confirmation per topic,
+/// counting subscriptions cumulatively across both lists as Redis does.
+async fn topic_stream(
+    state: AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
+    channel_list: Vec<String>,
+    pattern_list: Vec<String>,
+    params: StreamParams,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let format = params.format;
+    let enc = payload_base64(&headers, &params);
+    if channel_list.is_empty() && pattern_list.is_empty() {
+        return Err(malformed("No channels or patterns specified".into()));
+    }
+    if params.durable && !state.config.durable.enabled {
+        return Err(malformed(
+            "Durable streams are disabled on this server.".into(),
+        ));
+    }
+    if params.durable && !pattern_list.is_empty() {
+        return Err(malformed("Durable streams can't follow patterns.".into()));
+    }
+    let last_event_id = match headers.get("last-event-id") {
+        Some(v) if params.durable => Some(v.to_str().map_err(|e| malformed(e.to_string()))?),
+        _ => None,
+    };
+
+    let mut cmds = Vec::new();
+    for (name, list) in [("SUBSCRIBE", &channel_list), ("PSUBSCRIBE", &pattern_list)] {
+        if !list.is_empty() {
+            let mut cmd = vec![name.to_string()];
+            cmd.extend(list.iter().cloned());
+            cmds.push(cmd);
+        }
+    }
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize_subscribe(&state, principal, log, &cmds, enc) {
+        return Err(resp);
+    }
+
+    // Durable subscribers find their place in the recorded history before
+    // subscribing, and then listen for announcements that carry entry IDs
+    let mut cursor = None;
+    if params.durable {
+        let deadline = state.config.timeouts.command();
+        let res = match state.conn.connection() {
+            Ok(mut conn) => {
+                let config = &state.config.durable;
+                durable::Cursor::start(&mut conn, config, &channel_list, last_event_id, deadline)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(c) => cursor = Some(c),
+            Err(e) if e.is::<durable::InvalidEventId>() => return Err(malformed(e.to_string())),
+            Err(e) => {
+                log.set_error(e.to_string());
+                return Err(write_resp(
+                    EnvResp {
+                        status: error_status(&e),
+                        result: None,
+                        result_list: None,
+                        error: Some(format!("Failed to read message history: {}", e)),
+                        message: None,
+                    },
+                    enc,
+                ));
+            }
+        }
+    }
+
+    // Join the shared subscriber connection, confirming each topic with
+    // the subscription's size so far; a repeated topic doesn't add to it
+    let mut sub = state.hub.subscription();
+    let mut confirmations = Vec::new();
+    let topics = channel_list
+        .into_iter()
+        .map(Topic::Channel)
+        .chain(pattern_list.into_iter().map(Topic::Pattern));
+    for topic in topics {
+        let joined = match &topic {
+            Topic::Channel(channel) if params.durable => {
+                Topic::Channel(durable::stream_key(&state.config.durable, channel))
+            }
+            topic => topic.clone(),
+        };
+        if let Err(e) = sub.add(joined).await {
+            let verb = match topic {
+                Topic::Pattern(_) => "psubscribe",
+                _ => "subscribe",
+            };
+            log.set_error(e.to_string());
             return Err(write_resp(
                 EnvResp {
-                    status: "connection_error".into(),
+                    status: error_status(&e),
                     result: None,
                     result_list: None,
-                    error: Some(format!("Failed to create pubsub connection: {}", e)),
+                    error: Some(format!("Failed to {}: {}", verb, e)),
                     message: None,
                 },
-                false,
+                enc,
             ));
         }
+        let count = sub.len();
+        confirmations.push(match topic {
+            Topic::Pattern(pattern) => PubSubMessage::PSubscribe { pattern, count },
+            Topic::Channel(channel) | Topic::Shard(channel) => {
+                PubSubMessage::Subscribe { channel, count }
+            }
+        });
+    }
+
+    // Create the SSE stream
+    let shutdown = state.shutdown.clone();
+    let stream = async_stream::stream! {
+        let _guard = SseGuard::new(confirmations.len());
+
+        for confirmation in &confirmations {
+            yield Ok(sse_event(confirmation, format));
+        }
+        let mut catch_up = cursor.is_some();
+
+        // Stream messages until the connection drops or the server shuts down
+        loop {
+            // Replay what a durable subscriber missed, whether before it
+            // connected or while the subscriber connection was down
+            if let (Some(cursor), true) = (cursor.as_mut(), catch_up) {
+                catch_up = false;
+                let deadline = state.config.timeouts.command();
+                let res = match state.conn.connection() {
+                    Ok(mut conn) => cursor.catch_up(&mut conn, deadline).await,
+                    Err(e) => Err(e),
+                };
+                match res {
+                    Ok(entries) => {
+                        for entry in entries {
+                            let event = sse_event(&entry.message(enc), format);
+                            yield Ok(event.id(cursor.advance(&entry)));
+                        }
+                    }
+                    Err(e) => tracing::warn!(error = %e, "failed to replay durable messages"),
+                }
+            }
+            let next = tokio::select! {
+                msg = sub.next() => Some(msg),
+                _ = shutdown.wait() => None,
+            };
+            let Some(msg) = next else {
+                yield Ok(reconnect_event(format));
+                break;
+            };
+            match msg {
+                Some(Signal::Message(msg)) => match cursor.as_mut() {
+                    Some(cursor) => {
+                        if let Some(entry) = cursor.accept(&msg) {
+                            let event = sse_event(&entry.message(enc), format);
+                            yield Ok(event.id(cursor.event_id()));
+                        }
+                    }
+                    None => yield Ok(sse_event(&msg.message(enc), format)),
+                },
+                Some(Signal::Reconnecting) => yield Ok(reconnecting_event(format)),
+                Some(Signal::Resubscribed) => {
+                    catch_up = true;
+                    yield Ok(resubscribed_event(format));
+                }
+                // Durable subscribers get the dropped messages from history
+                Some(Signal::Lagged { missed }) => {
+                    catch_up = true;
+                    yield Ok(lagged_event(format, missed));
+                }
+                None => break,
+            }
+        }
     };
 
-    // Subscribe to patterns
-    match psubscribe_to_patterns(&mut pubsub, &pattern_list).await {
-        Ok(_) => {},
-        Err(e) => {
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn get_ssubscribe(
+    state: State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    channels: Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = ssubscribe_stream(state, &principal, &log, headers, channels, params).await;
+    observe_stream("/ssubscribe", "ssubscribe", &res, start);
+    res
+}
+
+async fn ssubscribe_stream(
+    State(state): State<AppState>,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: HeaderMap,
+    Path(channels): Path<String>,
+    params: Result<Query<StreamParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let params = stream_params(params).map_err(malformed)?;
+    let format = params.format;
+    let enc = payload_base64(&headers, &params);
+    if params.durable {
+        return Err(malformed(
+            "Durable streams are only available on /subscribe.".into(),
+        ));
+    }
+    let channel_list: Vec<String> = channels
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    if channel_list.is_empty() {
+        return Err(malformed("No channels specified".into()));
+    }
+
+    let mut cmd = vec!["SSUBSCRIBE".to_string()];
+    cmd.extend(channel_list.iter().cloned());
+    let cmds = [cmd];
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize_subscribe(&state, principal, log, &cmds, enc) {
+        return Err(resp);
+    }
+
+    // Shard channels share their own subscriber connection. Each is
+    // confirmed with the subscription's size so far, as in `topic_stream`.
+    let mut sub = state.hub.subscription();
+    let mut confirmations = Vec::new();
+    for channel in &channel_list {
+        if let Err(e) = sub.add(Topic::Shard(channel.clone())).await {
+            log.set_error(e.to_string());
             return Err(write_resp(
                 EnvResp {
-                    status: "error".into(),
+                    status: error_status(&e),
                     result: None,
                     result_list: None,
-                    error: Some(format!("Failed to psubscribe: {}", e)),
+                    error: Some(format!("Failed to ssubscribe: {}", e)),
                     message: None,
                 },
-                false,
+                enc,
             ));
         }
-    };
+        confirmations.push(PubSubMessage::SSubscribe {
+            channel: channel.clone(),
+            count: sub.len(),
+        });
+    }
 
-    // Create the SSE stream
+    let shutdown = state.shutdown.clone();
     let stream = async_stream::stream! {
-        // Send initial psubscribe events - one per pattern with incrementing count
-        for (idx, pattern) in pattern_list.iter().enumerate() {
-            let psubscribe_msg = PubSubMessage::PSubscribe { 
-                pattern: pattern.clone(), 
-                count: idx + 1 
-            };
-            yield Ok(Event::default().data(format_sse_message(&psubscribe_msg)));
+        let _guard = SseGuard::new(channel_list.len());
+
+        for confirmation in &confirmations {
+            yield Ok(sse_event(confirmation, format));
         }
 
-        // Stream messages
-        let mut message_stream = pubsub.on_message();
-        while let Some(msg) = message_stream.next().await {
-            if let Some(parsed) = parse_redis_message(&msg) {
-                let sse_data = format_sse_message(&parsed);
-                yield Ok(Event::default().data(sse_data));
+        loop {
+            let next = tokio::select! {
+                msg = sub.next() => Some(msg),
+                _ = shutdown.wait() => None,
+            };
+            let Some(msg) = next else {
+                yield Ok(reconnect_event(format));
+                break;
+            };
+            match msg {
+                Some(Signal::Message(msg)) => yield Ok(sse_event(&msg.message(enc), format)),
+                Some(Signal::Reconnecting) => yield Ok(reconnecting_event(format)),
+                Some(Signal::Resubscribed) => yield Ok(resubscribed_event(format)),
+                Some(Signal::Lagged { missed }) => yield Ok(lagged_event(format, missed)),
+                None => break,
             }
         }
     };
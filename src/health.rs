@@ -0,0 +1,130 @@
+use crate::models::AppState;
+use crate::pubsub::create_pubsub_connection;
+use crate::utils::redact_url;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use std::time::Instant;
+use tokio::time::timeout;
+
+/// Liveness: the process is up and serving HTTP. Never touches Redis.
+pub async fn get_healthz() -> impl IntoResponse {
+    (StatusCode::OK, Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Readiness: the primary answers PING, a pub/sub connection can be opened
+/// and answers PING, both within `timeouts.ready_ms`, and no shared
+/// subscriber connection is reconnecting. Replica health is reported but does
+/// not affect the result, since reads fall back to the primary. Always
+/// unready once shutdown has started. Error messages and replica URLs name
+/// hosts, so they are only included when probes require the token.
+pub async fn get_readyz(State(state): State<AppState>) -> impl IntoResponse {
+    if state.shutdown.is_triggered() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "shutting_down" })),
+        );
+    }
+    let redis = check_redis(&state).await;
+    let pubsub = check_pubsub(&state).await;
+    let ready = redis["status"] == "ok" && pubsub["status"] == "ok";
+
+    let mut body = serde_json::json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "redis": redis,
+        "pubsub": pubsub,
+    });
+    if let Some(replicas) = &state.replicas {
+        body["replicas"] = replicas
+            .replicas()
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let mut out = serde_json::json!({
+                    "index": i,
+                    "healthy": r.is_healthy(),
+                    "latency_ms": r.latency().as_secs_f64() * 1000.0,
+                });
+                // Hosts, like error text, only for token holders
+                if state.config.auth.probes_require_auth {
+                    out["url"] = redact_url(r.upstream.url()).into();
+                }
+                out
+            })
+            .collect();
+    }
+
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(body))
+}
+
+async fn check_redis(state: &AppState) -> serde_json::Value {
+    let mut conn = match state.conn.connection() {
+        Ok(conn) => conn,
+        Err(_) => {
+            let mut out = serde_json::json!({ "status": state.conn.status().as_str() });
+            if let Some(e) = state.conn.last_error() {
+                out["error"] = serde_json::Value::String(e);
+            }
+            return redact_errors(state, out);
+        }
+    };
+    let start = Instant::now();
+    let ping = redis::cmd("PING");
+    let deadline = state.config.timeouts.ready();
+    let out = match timeout(deadline, ping.query_async::<String>(&mut conn)).await {
+        Ok(Ok(_)) => serde_json::json!({
+            "status": "ok",
+            "latency_ms": start.elapsed().as_secs_f64() * 1000.0,
+        }),
+        Ok(Err(e)) => serde_json::json!({ "status": "error", "error": e.to_string() }),
+        Err(_) => serde_json::json!({ "status": "error", "error": "PING timed out" }),
+    };
+    redact_errors(state, out)
+}
+
+/// Drop the raw error from a check unless only token holders can see it
+fn redact_errors(state: &AppState, mut check: serde_json::Value) -> serde_json::Value {
+    if !state.config.auth.probes_require_auth {
+        if let Some(check) = check.as_object_mut() {
+            check.remove("error");
+        }
+    }
+    check
+}
+
+/// Open a pub/sub connection and PING it, then report it alongside the
+/// shared subscriber connections' state. The probe's connection is closed
+/// again straight away.
+async fn check_pubsub(state: &AppState) -> serde_json::Value {
+    let deadline = state.config.timeouts.ready();
+    let probe = async {
+        let mut pubsub = create_pubsub_connection(&state.redis_url).await?;
+        pubsub.ping::<String>().await?;
+        anyhow::Ok(())
+    };
+    let error = match timeout(deadline, probe).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("connect timed out".to_string()),
+    };
+    let hub = state.hub.status();
+    let status = if error.is_some() {
+        "error"
+    } else if hub.reconnecting > 0 {
+        "reconnecting"
+    } else {
+        "ok"
+    };
+    let mut out = serde_json::json!({
+        "status": status,
+        "connected": hub.connected,
+        "reconnecting": hub.reconnecting,
+    });
+    if let Some(e) = error {
+        out["error"] = serde_json::Value::String(e);
+    }
+    redact_errors(state, out)
+}
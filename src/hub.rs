@@ -0,0 +1,811 @@
+use crate::metrics::METRICS;
+use crate::pubsub::{
+    create_pubsub_connection, create_sharded_connection, encode_payload, PubSubMessage,
+};
+use crate::upstream::{is_unavailable, UpstreamUnavailable, INITIAL_BACKOFF, MAX_BACKOFF};
+use crate::utils::redact_url;
+use futures::stream::Stream;
+use redis::aio::{MultiplexedConnection, PubSub};
+use redis::{PushInfo, PushKind};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::timeout;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{StreamExt, StreamMap};
+
+/// Something a client can subscribe to
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Topic {
+    Channel(String),
+    Pattern(String),
+    /// A shard channel, for `SSUBSCRIBE`
+    Shard(String),
+}
+
+impl Topic {
+    /// The channel or pattern
+    pub fn name(&self) -> &str {
+        match self {
+            Topic::Channel(n) | Topic::Pattern(n) | Topic::Shard(n) => n,
+        }
+    }
+}
+
+/// A subscriber connection. The RESP2 `PubSub` client has no `SSUBSCRIBE`,
+/// so shard channels are served over RESP3, with messages as pushes.
+enum Link {
+    Classic(PubSub),
+    Sharded(MultiplexedConnection, mpsc::UnboundedReceiver<PushInfo>),
+}
+
+impl Link {
+    async fn open(redis_url: &str, sharded: bool) -> anyhow::Result<Self> {
+        if sharded {
+            let (conn, pushes) = create_sharded_connection(redis_url).await?;
+            return Ok(Link::Sharded(conn, pushes));
+        }
+        Ok(Link::Classic(create_pubsub_connection(redis_url).await?))
+    }
+}
+
+/// A message received from Redis, shared by every subscriber of its topic
+#[derive(Debug)]
+pub struct Delivery {
+    pub channel: String,
+    /// The pattern it matched, for `PSUBSCRIBE` topics
+    pub pattern: Option<String>,
+    pub payload: Vec<u8>,
+    /// Received on a shard channel
+    pub sharded: bool,
+}
+
+impl Delivery {
+    /// The message to send a client, with the payload as base64 if `base64`
+    pub fn message(&self, base64: bool) -> PubSubMessage {
+        let channel = self.channel.clone();
+        let (payload, base64) = encode_payload(&self.payload, base64);
+        match &self.pattern {
+            None if self.sharded => PubSubMessage::SMessage {
+                channel,
+                payload,
+                base64,
+            },
+            Some(pattern) => PubSubMessage::PMessage {
+                pattern: pattern.clone(),
+                channel,
+                payload,
+                base64,
+            },
+            None => PubSubMessage::Message {
+                channel,
+                payload,
+                base64,
+            },
+        }
+    }
+}
+
+/// What a subscriber receives
+pub enum Signal {
+    Message(Arc<Delivery>),
+    /// The subscriber connection to Redis was lost; messages published
+    /// until `Resubscribed` are missed
+    Reconnecting,
+    /// Reconnected and subscribed to every topic again
+    Resubscribed,
+    /// This subscriber fell behind and `missed` messages were dropped
+    Lagged {
+        missed: u64,
+    },
+}
+
+/// What goes over a topic's broadcast channel. Connection events carry the
+/// connection and its reconnect count, so a subscription following several
+/// topics on that connection reports each event once.
+#[derive(Clone)]
+enum Broadcast {
+    Message(Arc<Delivery>),
+    Reconnecting { conn: usize, epoch: u64 },
+    Resubscribed { conn: usize, epoch: u64 },
+}
+
+/// Work for a connection's driver task, applied in the order it was queued
+enum Op {
+    Subscribe(Topic, oneshot::Sender<redis::RedisResult<()>>),
+    Unsubscribe(Topic),
+}
+
+struct Entry {
+    /// Tells a topic apart from an earlier one with the same name, which
+    /// may have been dropped and re-created since a subscriber joined it
+    id: u64,
+    conn: usize,
+    tx: broadcast::Sender<Broadcast>,
+    refs: usize,
+    /// Whether Redis confirmed the subscription, once it has answered.
+    /// Subscribers joining before then wait for it.
+    subscribed: watch::Receiver<Outcome>,
+}
+
+struct Conn {
+    /// Serves shard channels rather than channels and patterns
+    sharded: bool,
+    /// `None` until connected, and again after the connection is lost
+    ops: Mutex<Option<mpsc::UnboundedSender<Op>>>,
+    connecting: tokio::sync::Mutex<()>,
+    /// Cleared while reconnecting
+    up: AtomicBool,
+}
+
+/// How the subscriber connections are doing, for readiness probes.
+/// Connections open on first use, so an idle hub has none connected.
+pub struct HubStatus {
+    pub connected: usize,
+    /// Lost and being re-established
+    pub reconnecting: usize,
+}
+
+/// Shares a few Redis subscriber connections between every SSE and
+/// WebSocket subscriber.
+///
+/// Each channel or pattern is subscribed on Redis once, by the first client
+/// that asks for it, and unsubscribed when the last one leaves; messages fan
+/// out to clients over a broadcast channel per topic. Topics are spread over
+/// the connections by hash, except shard channels, which share one extra
+/// RESP3 connection opened when first needed. A lost connection is re-established with
+/// backoff and its topics subscribed again. Cloning is cheap; all clones share the same
+/// connections.
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    redis_url: String,
+    connect_timeout: Duration,
+    buffer: usize,
+    topics: Mutex<HashMap<Topic, Entry>>,
+    conns: Vec<Conn>,
+    next_id: AtomicU64,
+}
+
+impl Hub {
+    pub fn new(
+        redis_url: impl Into<String>,
+        connect_timeout: Duration,
+        connections: usize,
+        buffer: usize,
+    ) -> Self {
+        // One more connection than asked for, for shard channels
+        let shared = connections.max(1);
+        let conns = (0..=shared)
+            .map(|i| Conn {
+                sharded: i == shared,
+                ops: Mutex::new(None),
+                connecting: tokio::sync::Mutex::new(()),
+                up: AtomicBool::new(false),
+            })
+            .collect();
+        Hub {
+            inner: Arc::new(Inner {
+                redis_url: redis_url.into(),
+                connect_timeout,
+                buffer: buffer.max(1),
+                topics: Mutex::new(HashMap::new()),
+                conns,
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// An empty subscription; topics are added with `Subscription::add`
+    pub fn subscription(&self) -> Subscription {
+        Subscription {
+            hub: self.clone(),
+            topics: BTreeMap::new(),
+            streams: StreamMap::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    pub fn status(&self) -> HubStatus {
+        let mut status = HubStatus {
+            connected: 0,
+            reconnecting: 0,
+        };
+        for conn in &self.inner.conns {
+            if conn.up.load(Ordering::Relaxed) {
+                status.connected += 1;
+            } else if conn.ops.lock().unwrap().is_some() {
+                status.reconnecting += 1;
+            }
+        }
+        status
+    }
+
+    /// Drop every subscriber connection. Open subscriptions end.
+    pub fn close(&self) {
+        for conn in &self.inner.conns {
+            conn.ops.lock().unwrap().take();
+        }
+    }
+
+    fn conn_index(&self, topic: &Topic) -> usize {
+        let shared = self.inner.conns.len() - 1;
+        if let Topic::Shard(_) = topic {
+            return shared;
+        }
+        let mut h = DefaultHasher::new();
+        topic.hash(&mut h);
+        (h.finish() % shared as u64) as usize
+    }
+
+    /// The op queue of connection `idx`, connecting first if needed
+    async fn ops(&self, idx: usize) -> anyhow::Result<mpsc::UnboundedSender<Op>> {
+        let conn = &self.inner.conns[idx];
+        let live = || {
+            conn.ops
+                .lock()
+                .unwrap()
+                .as_ref()
+                .filter(|tx| !tx.is_closed())
+                .cloned()
+        };
+        if let Some(tx) = live() {
+            return Ok(tx);
+        }
+        let _connecting = conn.connecting.lock().await;
+        if let Some(tx) = live() {
+            return Ok(tx);
+        }
+        let link = match timeout(
+            self.inner.connect_timeout,
+            Link::open(&self.inner.redis_url, conn.sharded),
+        )
+        .await
+        {
+            Ok(link) => link?,
+            Err(_) => anyhow::bail!("timeout after {:?}", self.inner.connect_timeout),
+        };
+        tracing::debug!(
+            url = %redact_url(&self.inner.redis_url),
+            conn = idx,
+            "opened pub/sub connection"
+        );
+        let (tx, rx) = mpsc::unbounded_channel();
+        *conn.ops.lock().unwrap() = Some(tx.clone());
+        conn.up.store(true, Ordering::Relaxed);
+        tokio::spawn(self.clone().drive(idx, link, rx));
+        Ok(tx)
+    }
+
+    /// Run connection `idx` until the hub is closed, reconnecting and
+    /// resubscribing whenever it is lost
+    async fn drive(self, idx: usize, mut link: Link, mut ops: mpsc::UnboundedReceiver<Op>) {
+        let conn = &self.inner.conns[idx];
+        let mut epoch = 0;
+        while self.serve(idx, link, &mut ops).await {
+            epoch += 1;
+            conn.up.store(false, Ordering::Relaxed);
+            METRICS.pubsub_reconnects.inc();
+            self.notify(idx, Broadcast::Reconnecting { conn: idx, epoch });
+            match self.reconnect(idx).await {
+                Some(l) => link = l,
+                None => break,
+            }
+            conn.up.store(true, Ordering::Relaxed);
+            self.notify(idx, Broadcast::Resubscribed { conn: idx, epoch });
+        }
+        // Closed: end every subscription on this connection
+        conn.ops.lock().unwrap().take();
+        conn.up.store(false, Ordering::Relaxed);
+        let mut topics = self.inner.topics.lock().unwrap();
+        topics.retain(|_, e| e.conn != idx);
+        METRICS.pubsub_topics.set(topics.len() as i64);
+    }
+
+    /// Apply queued (un)subscribes and dispatch incoming messages. Returns
+    /// `true` if the connection was lost, `false` once the hub is closed.
+    async fn serve(&self, idx: usize, link: Link, ops: &mut mpsc::UnboundedReceiver<Op>) -> bool {
+        let lost = match link {
+            Link::Classic(pubsub) => self.serve_classic(pubsub, ops).await,
+            Link::Sharded(conn, pushes) => self.serve_sharded(conn, pushes, ops).await,
+        };
+        if lost {
+            tracing::warn!(
+                url = %redact_url(&self.inner.redis_url),
+                conn = idx,
+                "lost pub/sub connection"
+            );
+        }
+        lost
+    }
+
+    async fn serve_classic(&self, pubsub: PubSub, ops: &mut mpsc::UnboundedReceiver<Op>) -> bool {
+        let (mut sink, mut stream) = pubsub.split();
+        loop {
+            tokio::select! {
+                op = ops.recv() => match op {
+                    Some(Op::Subscribe(topic, done)) => {
+                        let res = match &topic {
+                            Topic::Channel(c) => sink.subscribe(c).await,
+                            Topic::Pattern(p) => sink.psubscribe(p).await,
+                            Topic::Shard(_) => unreachable!("shard channels have their own connection"),
+                        };
+                        let _ = done.send(res);
+                    }
+                    Some(Op::Unsubscribe(topic)) => {
+                        let res = match &topic {
+                            Topic::Channel(c) => sink.unsubscribe(c).await,
+                            Topic::Pattern(p) => sink.punsubscribe(p).await,
+                            Topic::Shard(_) => unreachable!("shard channels have their own connection"),
+                        };
+                        if let Err(e) = res {
+                            tracing::warn!(?topic, error = %e, "failed to unsubscribe");
+                        }
+                    }
+                    None => return false,
+                },
+                msg = stream.next() => match msg {
+                    Some(msg) => self.dispatch(&msg),
+                    None => return true,
+                },
+            }
+        }
+    }
+
+    async fn serve_sharded(
+        &self,
+        mut conn: MultiplexedConnection,
+        mut pushes: mpsc::UnboundedReceiver<PushInfo>,
+        ops: &mut mpsc::UnboundedReceiver<Op>,
+    ) -> bool {
+        loop {
+            tokio::select! {
+                op = ops.recv() => match op {
+                    Some(Op::Subscribe(topic, done)) => {
+                        let res = redis::cmd("SSUBSCRIBE")
+                            .arg(topic.name())
+                            .exec_async(&mut conn)
+                            .await;
+                        let _ = done.send(res);
+                    }
+                    Some(Op::Unsubscribe(topic)) => {
+                        let res = redis::cmd("SUNSUBSCRIBE")
+                            .arg(topic.name())
+                            .exec_async(&mut conn)
+                            .await;
+                        if let Err(e) = res {
+                            tracing::warn!(?topic, error = %e, "failed to unsubscribe");
+                        }
+                    }
+                    None => return false,
+                },
+                push = pushes.recv() => match push {
+                    Some(push) if push.kind == PushKind::Disconnection => return true,
+                    Some(push) => self.dispatch_shard(push),
+                    None => return true,
+                },
+            }
+        }
+    }
+
+    /// Reconnect with exponential backoff, resubscribing to the topics on
+    /// connection `idx`. `None` if the hub was closed meanwhile.
+    async fn reconnect(&self, idx: usize) -> Option<Link> {
+        let mut delay = INITIAL_BACKOFF;
+        loop {
+            if self.inner.conns[idx].ops.lock().unwrap().is_none() {
+                return None;
+            }
+            let attempt = timeout(self.inner.connect_timeout, self.resubscribe(idx)).await;
+            let e = match attempt {
+                Ok(Ok((link, topics))) => {
+                    tracing::info!(
+                        url = %redact_url(&self.inner.redis_url),
+                        conn = idx,
+                        topics,
+                        "reconnected pub/sub connection"
+                    );
+                    return Some(link);
+                }
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("timeout after {:?}", self.inner.connect_timeout),
+            };
+            tracing::warn!(
+                url = %redact_url(&self.inner.redis_url),
+                conn = idx,
+                error = %e,
+                retry_in_ms = delay.as_millis() as u64,
+                "failed to reconnect pub/sub connection"
+            );
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Open a new connection subscribed to every topic on connection `idx`
+    async fn resubscribe(&self, idx: usize) -> anyhow::Result<(Link, usize)> {
+        let (mut channels, mut patterns, mut shards) = (Vec::new(), Vec::new(), Vec::new());
+        for (topic, entry) in self.inner.topics.lock().unwrap().iter() {
+            match topic {
+                _ if entry.conn != idx => {}
+                Topic::Channel(c) => channels.push(c.clone()),
+                Topic::Pattern(p) => patterns.push(p.clone()),
+                Topic::Shard(c) => shards.push(c.clone()),
+            }
+        }
+        let topics = channels.len() + patterns.len() + shards.len();
+        if self.inner.conns[idx].sharded {
+            let (mut conn, pushes) = create_sharded_connection(&self.inner.redis_url).await?;
+            // One at a time: a cluster refuses shard channels of different
+            // slots in one command
+            for shard in &shards {
+                redis::cmd("SSUBSCRIBE")
+                    .arg(shard)
+                    .exec_async(&mut conn)
+                    .await?;
+            }
+            return Ok((Link::Sharded(conn, pushes), topics));
+        }
+        let mut pubsub = create_pubsub_connection(&self.inner.redis_url).await?;
+        if !channels.is_empty() {
+            pubsub.subscribe(&channels).await?;
+        }
+        if !patterns.is_empty() {
+            pubsub.psubscribe(&patterns).await?;
+        }
+        Ok((Link::Classic(pubsub), topics))
+    }
+
+    /// Send a connection event to every topic on connection `idx`
+    fn notify(&self, idx: usize, event: Broadcast) {
+        for entry in self.inner.topics.lock().unwrap().values() {
+            if entry.conn == idx {
+                let _ = entry.tx.send(event.clone());
+            }
+        }
+    }
+
+    fn dispatch(&self, msg: &redis::Msg) {
+        let channel = msg.get_channel_name().to_string();
+        let pattern = if msg.from_pattern() {
+            msg.get_pattern::<String>().ok()
+        } else {
+            None
+        };
+        let topic = match &pattern {
+            Some(p) => Topic::Pattern(p.clone()),
+            None => Topic::Channel(channel.clone()),
+        };
+        let topics = self.inner.topics.lock().unwrap();
+        if let Some(entry) = topics.get(&topic) {
+            let _ = entry.tx.send(Broadcast::Message(Arc::new(Delivery {
+                channel,
+                pattern,
+                payload: msg.get_payload_bytes().to_vec(),
+                sharded: false,
+            })));
+        }
+    }
+
+    /// Dispatch an `smessage` push; other pushes, such as `ssubscribe`
+    /// replies, are ignored
+    fn dispatch_shard(&self, push: PushInfo) {
+        if push.kind != PushKind::SMessage {
+            return;
+        }
+        let mut data = push.data.into_iter();
+        let (Some(channel), Some(payload)) = (data.next(), data.next()) else {
+            return;
+        };
+        let (Ok(channel), Ok(payload)) = (
+            redis::from_redis_value::<String>(channel),
+            redis::from_redis_value::<Vec<u8>>(payload),
+        ) else {
+            return;
+        };
+        let topics = self.inner.topics.lock().unwrap();
+        if let Some(entry) = topics.get(&Topic::Shard(channel.clone())) {
+            let _ = entry.tx.send(Broadcast::Message(Arc::new(Delivery {
+                channel,
+                pattern: None,
+                payload,
+                sharded: true,
+            })));
+        }
+    }
+
+    /// Join `topic`, subscribing on Redis if nobody else has. Everyone
+    /// joining before Redis answers gets the outcome of that one subscribe.
+    async fn acquire(
+        &self,
+        topic: &Topic,
+    ) -> anyhow::Result<(u64, broadcast::Receiver<Broadcast>)> {
+        let idx = self.conn_index(topic);
+        let ops = self.ops(idx).await?;
+        let up = self.inner.conns[idx].up.load(Ordering::Relaxed);
+        let joined = {
+            let mut topics = self.inner.topics.lock().unwrap();
+            if let Some(entry) = topics.get_mut(topic) {
+                entry.refs += 1;
+                Join::Existing(entry.id, entry.tx.subscribe(), entry.subscribed.clone())
+            } else if !up {
+                return Err(UpstreamUnavailable {
+                    url: self.inner.redis_url.clone(),
+                }
+                .into());
+            } else {
+                let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+                let (tx, rx) = broadcast::channel(self.inner.buffer);
+                let (done_tx, done) = oneshot::channel();
+                // Queued under the lock so it can't overtake an unsubscribe
+                // of the same topic
+                if ops.send(Op::Subscribe(topic.clone(), done_tx)).is_err() {
+                    anyhow::bail!("pub/sub connection closed");
+                }
+                let (outcome, subscribed) = watch::channel(None);
+                topics.insert(
+                    topic.clone(),
+                    Entry {
+                        id,
+                        conn: idx,
+                        tx,
+                        refs: 1,
+                        subscribed,
+                    },
+                );
+                METRICS.pubsub_topics.set(topics.len() as i64);
+                Join::First(id, rx, done, outcome)
+            }
+        };
+
+        let (id, rx, done, outcome) = match joined {
+            Join::First(id, rx, done, outcome) => (id, rx, done, outcome),
+            Join::Existing(id, rx, mut subscribed) => {
+                // Someone else is subscribing; its outcome is ours too
+                let res = match subscribed.wait_for(Option::is_some).await {
+                    Ok(res) => res.clone().expect("waited for an outcome"),
+                    Err(_) => Err(Failed {
+                        error: "pub/sub connection closed".into(),
+                        unavailable: false,
+                    }),
+                };
+                return match res {
+                    Ok(()) => Ok((id, rx)),
+                    Err(failed) => {
+                        self.release(topic, id);
+                        Err(failed.into_error(&self.inner.redis_url))
+                    }
+                };
+            }
+        };
+        // Give up our reference if the caller stops waiting
+        let pending = Pending {
+            hub: self,
+            topic,
+            id,
+        };
+        let res = match done.await {
+            Ok(res) => res.map_err(anyhow::Error::from),
+            Err(_) => Err(anyhow::anyhow!("pub/sub connection closed")),
+        };
+        std::mem::forget(pending);
+        match res {
+            Ok(()) => {
+                let _ = outcome.send(Some(Ok(())));
+                Ok((id, rx))
+            }
+            Err(e) => {
+                // Forget the topic for everyone, so the next subscriber to
+                // ask for it tries again
+                self.forget(topic, id);
+                let _ = outcome.send(Some(Err(Failed {
+                    error: e.to_string(),
+                    unavailable: is_unavailable(&e),
+                })));
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop the entry for `topic` without unsubscribing, after the
+    /// subscribe that created it failed
+    fn forget(&self, topic: &Topic, id: u64) {
+        let mut topics = self.inner.topics.lock().unwrap();
+        if topics.get(topic).is_some_and(|e| e.id == id) {
+            topics.remove(topic);
+            METRICS.pubsub_topics.set(topics.len() as i64);
+        }
+    }
+
+    /// Leave `topic`, unsubscribing on Redis once nobody is left
+    fn release(&self, topic: &Topic, id: u64) {
+        let mut topics = self.inner.topics.lock().unwrap();
+        let Some(entry) = topics.get_mut(topic).filter(|e| e.id == id) else {
+            return;
+        };
+        entry.refs -= 1;
+        if entry.refs > 0 {
+            return;
+        }
+        let conn = entry.conn;
+        topics.remove(topic);
+        METRICS.pubsub_topics.set(topics.len() as i64);
+        if let Some(ops) = self.inner.conns[conn].ops.lock().unwrap().as_ref() {
+            let _ = ops.send(Op::Unsubscribe(topic.clone()));
+        }
+    }
+}
+
+/// Whether Redis confirmed a topic's subscribe, once it has answered
+type Outcome = Option<Result<(), Failed>>;
+
+/// A failed subscribe, as everyone who joined the topic meanwhile sees it
+#[derive(Clone)]
+struct Failed {
+    error: String,
+    /// Redis couldn't be reached, rather than refusing the subscribe
+    unavailable: bool,
+}
+
+impl Failed {
+    fn into_error(self, redis_url: &str) -> anyhow::Error {
+        if self.unavailable {
+            UpstreamUnavailable {
+                url: redis_url.to_string(),
+            }
+            .into()
+        } else {
+            anyhow::anyhow!(self.error)
+        }
+    }
+}
+
+/// How `acquire` joined a topic
+enum Join {
+    /// Created it, and has to wait for Redis to confirm the subscribe
+    First(
+        u64,
+        broadcast::Receiver<Broadcast>,
+        oneshot::Receiver<redis::RedisResult<()>>,
+        watch::Sender<Outcome>,
+    ),
+    /// Found it, confirmed or still waiting for whoever created it
+    Existing(
+        u64,
+        broadcast::Receiver<Broadcast>,
+        watch::Receiver<Outcome>,
+    ),
+}
+
+/// The first subscriber's reference to a topic while Redis hasn't answered
+/// its subscribe yet; released if `acquire` is dropped meanwhile
+struct Pending<'a> {
+    hub: &'a Hub,
+    topic: &'a Topic,
+    id: u64,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.hub.release(self.topic, self.id);
+    }
+}
+
+enum Item {
+    Broadcast(Broadcast),
+    Lagged(u64),
+    Closed,
+}
+
+type ItemStream = Pin<Box<dyn Stream<Item = Item> + Send>>;
+
+/// One client's set of topics on the hub. Dropping it leaves them all.
+pub struct Subscription {
+    hub: Hub,
+    topics: BTreeMap<Topic, u64>,
+    streams: StreamMap<Topic, ItemStream>,
+    /// The last connection event reported per connection, as `(epoch,
+    /// resubscribed)`
+    reported: HashMap<usize, (u64, bool)>,
+}
+
+impl Subscription {
+    /// Join `topic`; returns `false` if this subscription already has it
+    pub async fn add(&mut self, topic: Topic) -> anyhow::Result<bool> {
+        if self.topics.contains_key(&topic) {
+            return Ok(false);
+        }
+        let (id, rx) = self.hub.acquire(&topic).await?;
+        let stream = BroadcastStream::new(rx)
+            .map(|r| match r {
+                Ok(b) => Item::Broadcast(b),
+                Err(BroadcastStreamRecvError::Lagged(n)) => Item::Lagged(n),
+            })
+            .chain(futures::stream::once(async { Item::Closed }));
+        self.streams.insert(topic.clone(), Box::pin(stream));
+        self.topics.insert(topic, id);
+        Ok(true)
+    }
+
+    /// Leave `topic`; returns `false` if this subscription didn't have it
+    pub fn remove(&mut self, topic: &Topic) -> bool {
+        let Some(id) = self.topics.remove(topic) else {
+            return false;
+        };
+        self.streams.remove(topic);
+        self.hub.release(topic, id);
+        true
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &Topic> {
+        self.topics.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// The next message or connection event on any topic. Waits forever
+    /// while there are no topics; `None` means the hub was closed and every
+    /// topic is gone.
+    pub async fn next(&mut self) -> Option<Signal> {
+        loop {
+            if self.streams.is_empty() {
+                std::future::pending::<()>().await;
+            }
+            let (conn, event, signal) = match self.streams.next().await {
+                Some((_, Item::Broadcast(Broadcast::Message(d)))) => {
+                    return Some(Signal::Message(d))
+                }
+                Some((_, Item::Broadcast(Broadcast::Reconnecting { conn, epoch }))) => {
+                    (conn, (epoch, false), Signal::Reconnecting)
+                }
+                Some((_, Item::Broadcast(Broadcast::Resubscribed { conn, epoch }))) => {
+                    (conn, (epoch, true), Signal::Resubscribed)
+                }
+                Some((topic, Item::Lagged(n))) => {
+                    tracing::debug!(?topic, missed = n, "subscriber fell behind");
+                    METRICS.pubsub_lagged.inc_by(n);
+                    return Some(Signal::Lagged { missed: n });
+                }
+                Some((topic, Item::Closed)) => {
+                    // Only this topic's connection is gone
+                    self.streams.remove(&topic);
+                    self.topics.remove(&topic);
+                    if self.streams.is_empty() {
+                        return None;
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+            // Every topic on the connection carries the event; report it once
+            if self.reported.get(&conn).is_some_and(|r| *r >= event) {
+                continue;
+            }
+            self.reported.insert(conn, event);
+            return Some(signal);
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        for (topic, id) in std::mem::take(&mut self.topics) {
+            self.hub.release(&topic, id);
+        }
+    }
+}
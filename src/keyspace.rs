@@ -0,0 +1,232 @@
+use crate::config::KeyspaceConfig;
+use crate::handlers::{authorize, error_status, malformed, observe_stream};
+use crate::hub::{Signal, Topic};
+use crate::logging::RequestLog;
+use crate::metrics::SseGuard;
+use crate::models::{AppState, EnvResp};
+use crate::policy::Principal;
+use crate::pubsub::{
+    lagged_event, reconnect_event, reconnecting_event, resubscribed_event, SseFormat,
+};
+use crate::redis_client::do_call;
+use crate::telemetry::db_index;
+use crate::upstream::Upstream;
+use crate::utils::write_resp;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::Extension;
+use futures::stream::Stream;
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Which of the two notification channels a stream follows
+#[derive(Clone, Copy)]
+enum Kind {
+    /// `__keyspace@<db>__:<key>`, with the event as payload
+    Keyspace,
+    /// `__keyevent@<db>__:<event>`, with the key as payload
+    Keyevent,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Keyspace => "keyspace",
+            Kind::Keyevent => "keyevent",
+        }
+    }
+}
+
+/// Query parameters of the notification routes
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct KeyspaceParams {
+    /// Database to watch. Only the one in `REDIS_URL` is allowed, since
+    /// tokens can't be restricted to a database.
+    pub db: Option<i64>,
+}
+
+/// A key change decoded from a notification
+struct KeyEvent {
+    db: i64,
+    key: String,
+    event: String,
+}
+
+/// Decode a notification received on `channel`
+fn decode(channel: &str, payload: &[u8]) -> Option<KeyEvent> {
+    let (kind, rest) = channel.strip_prefix("__")?.split_once('@')?;
+    let (db, name) = rest.split_once("__:")?;
+    let db = db.parse().ok()?;
+    let payload = String::from_utf8_lossy(payload).into_owned();
+    let (key, event) = match kind {
+        "keyspace" => (name.to_string(), payload),
+        "keyevent" => (payload, name.to_string()),
+        _ => return None,
+    };
+    Some(KeyEvent { db, key, event })
+}
+
+/// Add the `K` and `E` flags and `config.classes` to
+/// `notify-keyspace-events`, keeping those already set. Runs once at startup,
+/// never on behalf of a request. Failing is not fatal: notifications may be
+/// set up already, or be impossible to change on a managed service.
+pub async fn enable_notifications(
+    upstream: &Upstream,
+    config: &KeyspaceConfig,
+    deadline: Duration,
+) {
+    let res = async {
+        let mut conn = upstream.wait_connected().await;
+        let current = do_call(
+            &mut conn,
+            vec![
+                "CONFIG".into(),
+                "GET".into(),
+                "notify-keyspace-events".into(),
+            ],
+            deadline,
+        )
+        .await?;
+        // `[name, value]`, or `{name: value}` over RESP3
+        let current = current
+            .get(1)
+            .or_else(|| current.get("notify-keyspace-events"))
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut flags = current.clone();
+        for c in "KE".chars().chain(config.classes.chars()) {
+            if !flags.contains(c) {
+                flags.push(c);
+            }
+        }
+        if flags != current {
+            let cmd = vec![
+                "CONFIG".into(),
+                "SET".into(),
+                "notify-keyspace-events".into(),
+                flags.clone(),
+            ];
+            do_call(&mut conn, cmd, deadline).await?;
+            tracing::info!(flags = %flags, "enabled keyspace notifications");
+        }
+        anyhow::Ok(())
+    };
+    if let Err(e) = res.await {
+        tracing::warn!(error = %e, "could not enable keyspace notifications");
+    }
+}
+
+pub async fn get_keyspace(
+    state: State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    patterns: Path<String>,
+    params: Result<Query<KeyspaceParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = notification_stream(state, principal, &log, Kind::Keyspace, patterns, params).await;
+    observe_stream("/keyspace", "keyspace", &res, start);
+    res
+}
+
+pub async fn get_keyevent(
+    state: State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    events: Path<String>,
+    params: Result<Query<KeyspaceParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = notification_stream(state, principal, &log, Kind::Keyevent, events, params).await;
+    observe_stream("/keyevent", "keyevent", &res, start);
+    res
+}
+
+async fn notification_stream(
+    State(state): State<AppState>,
+    principal: Arc<Principal>,
+    log: &RequestLog,
+    kind: Kind,
+    Path(patterns): Path<String>,
+    params: Result<Query<KeyspaceParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let Query(params) = params.map_err(|e| malformed(e.body_text()))?;
+    let db = db_index();
+    if params.db.is_some_and(|d| d != db) {
+        return Err(malformed(format!("Only database {} can be watched", db)));
+    }
+    let channels: Vec<String> = patterns
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|p| format!("__{}@{}__:{}", kind.name(), db, p))
+        .collect();
+    if channels.is_empty() {
+        return Err(malformed("No patterns specified".into()));
+    }
+
+    let mut cmd = vec!["PSUBSCRIBE".to_string()];
+    cmd.extend(channels.iter().cloned());
+    let cmds = [cmd];
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize(&state, &principal, log, &cmds, false) {
+        return Err(resp);
+    }
+
+    let mut sub = state.hub.subscription();
+    for channel in &channels {
+        if let Err(e) = sub.add(Topic::Pattern(channel.clone())).await {
+            log.set_error(e.to_string());
+            return Err(write_resp(
+                EnvResp {
+                    status: error_status(&e),
+                    result: None,
+                    result_list: None,
+                    error: Some(format!("Failed to subscribe: {}", e)),
+                    message: None,
+                },
+                false,
+            ));
+        }
+    }
+
+    let shutdown = state.shutdown.clone();
+    let stream = async_stream::stream! {
+        let _guard = SseGuard::new(channels.len());
+        loop {
+            let next = tokio::select! {
+                msg = sub.next() => Some(msg),
+                _ = shutdown.wait() => None,
+            };
+            let Some(msg) = next else {
+                yield Ok(reconnect_event(SseFormat::Json));
+                break;
+            };
+            match msg {
+                Some(Signal::Message(msg)) => {
+                    // Patterns can match keys the token may not see
+                    let Some(ev) = decode(&msg.channel, &msg.payload) else {
+                        continue;
+                    };
+                    if !principal.allows_key(&ev.key) {
+                        continue;
+                    }
+                    let data = json!({"db": ev.db, "key": ev.key, "event": ev.event});
+                    yield Ok(Event::default().data(data.to_string()));
+                }
+                Some(Signal::Reconnecting) => yield Ok(reconnecting_event(SseFormat::Json)),
+                Some(Signal::Resubscribed) => yield Ok(resubscribed_event(SseFormat::Json)),
+                Some(Signal::Lagged { missed }) => yield Ok(lagged_event(SseFormat::Json, missed)),
+                None => break,
+            }
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
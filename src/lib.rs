@@ -1,23 +1,54 @@
+pub mod commands;
+pub mod config;
+pub mod cors;
+pub mod durable;
+pub mod glob;
 pub mod handlers;
+pub mod health;
+pub mod hub;
+pub mod keyspace;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod policy;
 pub mod pubsub;
 pub mod redis_client;
+pub mod replicas;
+pub mod shutdown;
+pub mod streams;
+pub mod telemetry;
+pub mod tls;
+pub mod upstream;
 pub mod utils;
+pub mod ws;
 
-use crate::handlers::{get_psubscribe, get_subscribe, post_multi_exec, post_pipeline, post_root};
+use crate::handlers::{
+    get_psubscribe, get_ssubscribe, get_subscribe, get_subscribe_topics, post_multi_exec,
+    post_pipeline, post_publish, post_root, post_subscribe_topics,
+};
+use crate::health::{get_healthz, get_readyz};
+use crate::logging::{log_requests, RequestLog};
+use crate::metrics::{get_metrics, METRICS};
 use crate::models::{AppState, EnvResp};
+use crate::policy::AccessControl;
 use crate::utils::write_resp;
 use axum::{
+    extract::DefaultBodyLimit,
     http::{Request, StatusCode},
+    middleware,
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
+use std::sync::Arc;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::validate_request::{ValidateRequest, ValidateRequestHeaderLayer};
 
 #[derive(Clone)]
 struct BearerTokenValidator {
-    token: String,
+    access: Arc<AccessControl>,
 }
 
 impl<B> ValidateRequest<B> for BearerTokenValidator {
@@ -27,36 +58,46 @@ impl<B> ValidateRequest<B> for BearerTokenValidator {
         &mut self,
         request: &mut Request<B>,
     ) -> Result<(), axum::response::Response<Self::ResponseBody>> {
-        match request.headers().get(axum::http::header::AUTHORIZATION) {
-            Some(header_value) => {
-                if let Ok(auth_str) = header_value.to_str() {
-                    if let Some(token) = auth_str.strip_prefix("Bearer ") {
-                        if constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
-                            return Ok(());
-                        }
+        let log = request.extensions().get::<RequestLog>().cloned();
+        let header = request.headers().get(axum::http::header::AUTHORIZATION);
+        let token = header
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match self.access.authenticate(token) {
+            Some(principal) => {
+                if let Some(log) = log {
+                    if self.access.auth_enabled() {
+                        log.set_identity(principal.name.as_str());
                     }
                 }
+                request.extensions_mut().insert(principal);
+                Ok(())
+            }
+            None => {
+                if let Some(log) = log.filter(|_| header.is_some()) {
+                    log.set_identity("invalid");
+                }
+                METRICS.auth_failures.inc();
                 Err(StatusCode::UNAUTHORIZED.into_response())
             }
-            None => Err(StatusCode::UNAUTHORIZED.into_response()),
         }
     }
 }
 
-/// Constant-time comparison of two byte slices to prevent timing attacks
-fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
-    if a.len() != b.len() {
-        return false;
-    }
-    a.iter()
-        .zip(b.iter())
-        .map(|(x, y)| x ^ y)
-        .fold(0u8, |acc, diff| acc | diff)
-        == 0
-}
+/// Build the router from `state.config`. `/healthz` and `/readyz` skip
+/// bearer-token validation unless `auth.probes_require_auth` is set, so
+/// orchestrator probes can reach them. Tokens are checked against
+/// `state.access`, so a reload takes effect without rebuilding the router;
+/// with no tokens configured, nothing is authenticated.
+pub fn create_app(state: AppState) -> Router {
+    let config = state.config.clone();
+    let access = state.access.clone();
+    let probes = Router::new()
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .with_state(state.clone());
 
-pub fn create_app(state: AppState, token: String) -> Router {
-    Router::new()
+    let mut app = Router::new()
         .route(
             "/",
             get(|| async {
@@ -77,15 +118,102 @@ pub fn create_app(state: AppState, token: String) -> Router {
             "/ping",
             get(|| async { (axum::http::StatusCode::OK, "Pong") }),
         )
-        .route("/pipeline", post(post_pipeline))
-        .route("/multi-exec", post(post_multi_exec))
-        .route("/subscribe/{*channels}", get(get_subscribe).post(get_subscribe))
+        // Large pipelines may arrive compressed (`Content-Encoding`)
         .route(
-            "/psubscribe/{*patterns}",
-            get(get_psubscribe).post(get_psubscribe),
+            "/pipeline",
+            post(post_pipeline).layer(RequestDecompressionLayer::new()),
+        )
+        .route("/multi-exec", post(post_multi_exec));
+    if config.features.metrics {
+        app = app.route("/metrics", get(get_metrics));
+    }
+    if config.features.pubsub {
+        app = app.route("/publish/{*channels}", post(post_publish));
+    }
+    if config.features.streams {
+        app = app
+            .route("/xack/{stream}/{group}", post(streams::post_xack))
+            .route(
+                "/xclaim/{stream}/{group}/{consumer}",
+                post(streams::post_xclaim),
+            )
+            .route(
+                "/xautoclaim/{stream}/{group}/{consumer}",
+                post(streams::post_xautoclaim),
+            )
+            .route("/xpending/{stream}/{group}", get(streams::get_xpending));
+    }
+    let mut streams = Router::new();
+    if config.features.websocket {
+        streams = streams.route("/ws", get(ws::get_ws));
+    }
+    if config.features.pubsub {
+        streams = streams
+            .route(
+                "/subscribe",
+                get(get_subscribe_topics).post(post_subscribe_topics),
+            )
+            .route("/subscribe/{*channels}", get(get_subscribe).post(get_subscribe))
+            .route(
+                "/psubscribe/{*patterns}",
+                get(get_psubscribe).post(get_psubscribe),
+            )
+            .route(
+                "/ssubscribe/{*channels}",
+                get(get_ssubscribe).post(get_ssubscribe),
+            );
+    }
+    if config.features.keyspace {
+        streams = streams
+            .route("/keyspace/{*patterns}", get(keyspace::get_keyspace))
+            .route("/keyevent/{*events}", get(keyspace::get_keyevent));
+    }
+    if config.features.streams {
+        streams = streams.route(
+            "/xreadgroup/{group}/{consumer}/{*streams}",
+            get(streams::get_xreadgroup),
+        );
+    }
+    let app = app.with_state(state.clone());
+    let streams = streams.with_state(state);
+
+    let auth = ValidateRequestHeaderLayer::custom(BearerTokenValidator { access });
+    let (app, public) = if config.auth.probes_require_auth {
+        (app.merge(probes).layer(auth.clone()), None)
+    } else {
+        (app.layer(auth.clone()), Some(probes))
+    };
+    let streams = streams.layer(auth);
+    // CORS sits outside auth so preflights, which never carry a token, are
+    // answered directly and rejections still carry the CORS headers
+    let (app, streams) = match cors::layers(&config.cors) {
+        Some((base, with_credentials)) => (app.layer(base), streams.layer(with_credentials)),
+        None => (app, streams),
+    };
+    let app = match public {
+        Some(probes) => app.merge(streams).merge(probes),
+        None => app.merge(streams),
+    };
+    let compression = &config.compression;
+    let app = if compression.enabled {
+        // SSE streams must never be buffered by an encoder
+        let predicate = SizeAbove::new(compression.min_bytes)
+            .and(NotForContentType::SSE)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES);
+        app.layer(
+            CompressionLayer::new()
+                .gzip(compression.allows("gzip"))
+                .br(compression.allows("br"))
+                .zstd(compression.allows("zstd"))
+                .no_deflate()
+                .compress_when(predicate),
         )
-        .with_state(state)
-        .layer(ValidateRequestHeaderLayer::custom(BearerTokenValidator {
-            token,
-        }))
+    } else {
+        app
+    };
+    // The body limit applies after decompression, so compressed bodies can't
+    // expand past it
+    app.layer(DefaultBodyLimit::max(config.limits.max_body_bytes))
+        .layer(middleware::from_fn(log_requests))
 }
@@ -0,0 +1,253 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+pub static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Request logging behaviour, fixed at startup by `init`
+#[derive(Clone, Debug)]
+pub struct LogOptions {
+    /// Default filter when `RUST_LOG` is not set, e.g. `info` or `serverless_redis=debug`
+    pub level: String,
+    /// Emit JSON lines instead of human-readable text
+    pub json: bool,
+    /// Fraction of successful requests to log; errors are always logged
+    pub sample_rate: f64,
+    /// Log full command arguments instead of command names only
+    pub log_args: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            level: "info".into(),
+            json: true,
+            sample_rate: 1.0,
+            log_args: false,
+        }
+    }
+}
+
+static OPTIONS: OnceLock<LogOptions> = OnceLock::new();
+
+fn options() -> &'static LogOptions {
+    OPTIONS.get_or_init(LogOptions::default)
+}
+
+/// Install the global tracing subscriber. When `tracer` is set, spans are
+/// also exported over OpenTelemetry regardless of the log level.
+pub fn init(opts: LogOptions, tracer: Option<opentelemetry_sdk::trace::SdkTracer>) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(opts.level.as_str()));
+    let fmt = tracing_subscriber::fmt::layer().with_target(false);
+    let fmt = if opts.json {
+        fmt.json().flatten_event(true).boxed()
+    } else {
+        fmt.boxed()
+    };
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(tracer.map(|t| tracing_opentelemetry::layer().with_tracer(t)))
+        .init();
+    let _ = OPTIONS.set(opts);
+}
+
+/// Per-request fields filled in by the auth layer and handlers, read back
+/// by `log_requests` once the response is ready
+#[derive(Clone, Default)]
+pub struct RequestLog(Arc<Mutex<LogFields>>);
+
+#[derive(Default)]
+struct LogFields {
+    identity: Option<String>,
+    commands: Vec<String>,
+    error: Option<String>,
+}
+
+impl RequestLog {
+    /// Which token made the request. Never the secret itself.
+    pub fn set_identity(&self, identity: impl Into<String>) {
+        self.0.lock().unwrap().identity = Some(identity.into());
+    }
+
+    /// Record the commands of this request, redacting arguments unless
+    /// `log_args` is enabled
+    pub fn set_commands(&self, cmds: &[Vec<String>]) {
+        let log_args = options().log_args;
+        self.0.lock().unwrap().commands = cmds
+            .iter()
+            .map(|cmd| {
+                if log_args {
+                    cmd.join(" ")
+                } else {
+                    cmd.first()
+                        .map(|c| c.to_ascii_uppercase())
+                        .unwrap_or_default()
+                }
+            })
+            .collect();
+    }
+
+    /// Error text returned by Redis or the proxy
+    pub fn set_error(&self, error: impl Into<String>) {
+        self.0.lock().unwrap().error = Some(error.into());
+    }
+}
+
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(&REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128 && v.bytes().all(|b| b.is_ascii_graphic()))
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Collapse a path to its route prefix so channel names don't end up in logs
+fn route_of(path: &str) -> String {
+    match path.trim_start_matches('/').split('/').next() {
+        Some(first) if !first.is_empty() => format!("/{}", first),
+        _ => "/".into(),
+    }
+}
+
+fn sampled(rate: f64) -> bool {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    // splitmix64 over a counter gives a cheap, evenly spread sample
+    let mut z = COUNTER
+        .fetch_add(1, Ordering::Relaxed)
+        .wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z as f64 / u64::MAX as f64) < rate
+}
+
+/// Assign a request ID, echo it in `X-Request-Id`, and log one line per request.
+///
+/// The request runs inside an `http.request` span whose parent is taken from
+/// the W3C `traceparent`/`tracestate` headers.
+pub async fn log_requests(mut req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let id = request_id(&req);
+    let method = req.method().clone();
+    let route = route_of(req.uri().path());
+    let log = RequestLog::default();
+    req.extensions_mut().insert(log.clone());
+
+    let span = tracing::info_span!(
+        "http.request",
+        otel.name = %format!("{} {}", method, route),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = %route,
+        request_id = %id,
+        http.response.status_code = tracing::field::Empty,
+    );
+    // Fails only when no OpenTelemetry layer is installed
+    let _ = span.set_parent(crate::telemetry::extract_context(req.headers()));
+
+    let mut resp = next.run(req).instrument(span.clone()).await;
+    span.record("http.response.status_code", resp.status().as_u16());
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    if let Ok(v) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID.clone(), v);
+    }
+
+    let status = resp.status();
+    if status.is_success() && !sampled(options().sample_rate) {
+        return resp;
+    }
+    let fields = log.0.lock().unwrap();
+    let identity = fields.identity.as_deref().unwrap_or("anonymous");
+    let commands = fields.commands.join(", ");
+    let error = fields.error.as_deref().unwrap_or("");
+    let probe = matches!(route.as_str(), "/healthz" | "/readyz" | "/metrics");
+    macro_rules! emit {
+        ($level:ident) => {
+            tracing::$level!(
+                request_id = %id,
+                method = %method,
+                route = %route,
+                identity,
+                commands,
+                status = status.as_u16(),
+                latency_ms,
+                error,
+                "request"
+            )
+        };
+    }
+    if status.is_server_error() {
+        emit!(error);
+    } else if status.is_client_error() {
+        emit!(warn);
+    } else if probe {
+        emit!(debug);
+    } else {
+        emit!(info);
+    }
+    drop(fields);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_id(id: &str) -> Request {
+        Request::builder()
+            .header(&REQUEST_ID, id)
+            .body(Default::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_well_formed_request_ids() {
+        assert_eq!(request_id(&with_id("req-123")), "req-123");
+        assert_eq!(request_id(&with_id(&"x".repeat(128))), "x".repeat(128));
+    }
+
+    #[test]
+    fn replaces_missing_or_unsafe_request_ids() {
+        for id in [
+            request_id(&Request::new(Default::default())),
+            request_id(&with_id("")),
+            request_id(&with_id("has space")),
+            request_id(&with_id(&"x".repeat(129))),
+        ] {
+            assert!(uuid::Uuid::parse_str(&id).is_ok(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn routes_hide_channel_names() {
+        assert_eq!(route_of("/"), "/");
+        assert_eq!(route_of("/pipeline"), "/pipeline");
+        assert_eq!(route_of("/subscribe/secret-channel"), "/subscribe");
+        assert_eq!(route_of("/xack/orders/group"), "/xack");
+    }
+
+    #[test]
+    fn sampling_bounds() {
+        assert!((0..100).all(|_| sampled(1.0)));
+        assert!((0..100).all(|_| !sampled(0.0)));
+        let hits = (0..10_000).filter(|_| sampled(0.25)).count();
+        assert!((2_000..3_000).contains(&hits), "{}", hits);
+    }
+}
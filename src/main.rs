@@ -1,53 +1,225 @@
+use clap::Parser;
+use serverless_redis::commands::CommandTable;
+use serverless_redis::config::{Cli, Config};
 use serverless_redis::create_app;
+use serverless_redis::hub::Hub;
+use serverless_redis::keyspace::enable_notifications;
+use serverless_redis::logging;
 use serverless_redis::models::AppState;
-use std::env;
-use std::net::SocketAddr;
+use serverless_redis::policy::{self, AccessControl};
+use serverless_redis::replicas::ReplicaSet;
+use serverless_redis::shutdown::{self, Shutdown};
+use serverless_redis::telemetry;
+use serverless_redis::tls::TlsListener;
+use serverless_redis::upstream::Upstream;
+use serverless_redis::utils::redact_url;
+use std::future::IntoFuture;
+use std::sync::Arc;
 use std::time::Duration;
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
+fn main() {
     // Load .env file if present
     dotenvy::dotenv().ok();
 
-    let url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into());
-    println!("Connecting to Redis at: {}", url);
-    
-    let client = redis::Client::open(url.clone()).expect("Failed to create Redis client");
-    
-    // Add timeout for connection with clear error message
-    let conn = match tokio::time::timeout(
-        Duration::from_secs(5),
-        client.get_connection_manager()
-    ).await {
-        Ok(Ok(conn)) => {
-            println!("✓ Connected to Redis successfully");
-            conn
-        }
-        Ok(Err(e)) => {
-            eprintln!("✗ Failed to connect to Redis: {}", e);
-            eprintln!("  Make sure Redis is running at: {}", url);
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("✗ {:#}", e);
             std::process::exit(1);
         }
-        Err(_) => {
-            eprintln!("✗ Timeout connecting to Redis (5s)");
-            eprintln!("  Make sure Redis is running at: {}", url);
+    };
+    if cli.check_config {
+        println!("✓ Configuration is valid");
+        return;
+    }
+
+    let runtime = match config.runtime.worker_threads {
+        1 => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build(),
+        0 => tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build(),
+        n => tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(n)
+            .enable_all()
+            .build(),
+    };
+    let runtime = match runtime {
+        Ok(rt) => rt,
+        Err(e) => {
+            eprintln!("✗ failed to start runtime: {}", e);
             std::process::exit(1);
         }
     };
-    
-    let token = env::var("SR_TOKEN").unwrap_or_default();
-    if token.is_empty() {
-        println!("⚠ Warning: SR_TOKEN not set - authentication disabled");
+    runtime.block_on(run(cli, config));
+}
+
+async fn run(cli: Cli, config: Config) {
+    // OpenTelemetry export is enabled by pointing at an OTLP/HTTP collector
+    let (tracer_provider, tracer, otlp_error) = match config.tracing.otlp_endpoint.as_deref() {
+        Some(endpoint) => match telemetry::init_tracer(endpoint, &config.tracing.service_name) {
+            Ok((provider, tracer)) => (Some(provider), Some(tracer), None),
+            Err(e) => (None, None, Some(e)),
+        },
+        None => (None, None, None),
+    };
+    logging::init(config.logging.options(), tracer);
+    if let Some(e) = otlp_error {
+        tracing::error!(error = %e, "failed to set up OTLP exporter; tracing disabled");
+    } else if let Some(endpoint) = &config.tracing.otlp_endpoint {
+        tracing::info!(endpoint, "exporting traces over OTLP");
+    }
+
+    let url = config.redis.url.clone();
+    // Already validated by Config::load
+    let client = redis::Client::open(url.as_str()).expect("valid redis.url");
+    telemetry::set_db_index(client.get_connection_info().redis_settings().db());
+    tracing::info!(url = %redact_url(&url), "connecting to Redis");
+
+    // Connect in the background so the proxy can start before Redis does;
+    // requests get `connection_error` until the first connection succeeds.
+    let conn = Upstream::new(
+        url.clone(),
+        config.timeouts.connect(),
+        config.redis.pool_size,
+    );
+    conn.spawn();
+
+    let commands = Arc::new(CommandTable::default());
+    {
+        let conn = conn.clone();
+        let commands = commands.clone();
+        tokio::spawn(async move { commands.load_with_retry(&conn).await });
+    }
+    if config.keyspace.enable_notifications {
+        let conn = conn.clone();
+        let keyspace = config.keyspace.clone();
+        let deadline = config.timeouts.command();
+        tokio::spawn(async move { enable_notifications(&conn, &keyspace, deadline).await });
+    }
+
+    let replicas = if config.redis.replicas.is_empty() {
+        None
+    } else {
+        let strategy = config.redis.replica_strategy;
+        let set = Arc::new(ReplicaSet::new(
+            &config.redis.replicas,
+            strategy,
+            config.timeouts.connect(),
+            config.redis.pool_size,
+        ));
+        set.clone().spawn_health_checks(Duration::from_millis(
+            config.redis.replica_health_interval_ms,
+        ));
+        tracing::info!(
+            replicas = config.redis.replicas.len(),
+            strategy = ?strategy,
+            "routing read-only commands to replicas"
+        );
+        Some(set)
+    };
+
+    // SSE and WebSocket subscribers share these connections; opened on the
+    // first subscribe
+    let hub = Hub::new(
+        url.clone(),
+        config.timeouts.connect(),
+        config.redis.pubsub_connections,
+        config.limits.subscriber_buffer,
+    );
+
+    let tokens = config.auth.all_tokens();
+    if tokens.is_empty() {
+        tracing::warn!("no tokens configured - authentication disabled");
     } else {
-        println!("✓ Bearer token authentication enabled");
+        tracing::info!(tokens = tokens.len(), "bearer token authentication enabled");
     }
-    
-    let state = AppState { conn, redis_url: url };
-    let app = create_app(state, token);
 
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".into());
-    let addr: SocketAddr = format!("0.0.0.0:{}", port).parse().expect("valid address");
+    // Tokens and policy reload on SIGHUP or when the config file changes
+    let access = Arc::new(AccessControl::new(&config));
+    let config = Arc::new(config);
+    policy::spawn_reloader(access.clone(), cli, config.clone());
+
+    let addr = config.listen_addr().expect("validated listen address");
+    let drain = config.timeouts.shutdown();
+    let pool_size = config.redis.pool_size;
+    let tls_config = config.listener.tls.as_ref().map(|tls| {
+        serverless_redis::tls::load_config(&tls.cert_file, &tls.key_file)
+            .expect("validated by Config::load")
+    });
+    let tls = tls_config.is_some();
+    let shutdown = Shutdown::default();
+    let state = AppState {
+        conn: conn.clone(),
+        redis_url: url,
+        commands,
+        replicas: replicas.clone(),
+        config,
+        access,
+        shutdown: shutdown.clone(),
+        hub: hub.clone(),
+    };
+    let app = create_app(state);
+
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    println!("✓ Listening on http://{}", addr);
-    axum::serve(listener, app).await.unwrap();
+    tracing::info!(
+        %addr,
+        tls,
+        worker_threads = tokio::runtime::Handle::current().metrics().num_workers(),
+        pool_size,
+        "listening"
+    );
+    // HTTP/1.1 and HTTP/2 are both served: over TLS via ALPN, and in
+    // cleartext via h2c prior knowledge
+    let stop = shutdown.clone();
+    let mut server = match tls_config {
+        Some(tls_config) => tokio::spawn(
+            axum::serve(TlsListener::new(listener, tls_config).unwrap(), app)
+                .with_graceful_shutdown(async move { stop.wait().await })
+                .into_future(),
+        ),
+        None => tokio::spawn(
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async move { stop.wait().await })
+                .into_future(),
+        ),
+    };
+    tokio::select! {
+        res = &mut server => {
+            tracing::error!(result = ?res, "server exited unexpectedly");
+            std::process::exit(1);
+        }
+        _ = shutdown::signal() => {}
+    }
+
+    // Stop accepting connections, let SSE streams send their final
+    // `reconnect` event, and give in-flight requests until the deadline
+    tracing::info!(
+        deadline_ms = drain.as_millis() as u64,
+        "shutting down, draining requests"
+    );
+    shutdown.trigger();
+    let drained = async {
+        let _ = (&mut server).await;
+        // WebSocket sessions outlive the server's own tracking
+        shutdown.idle().await;
+    };
+    match tokio::time::timeout(drain, drained).await {
+        Ok(()) => tracing::info!("all connections closed"),
+        Err(_) => {
+            tracing::warn!("drain deadline passed, dropping remaining connections");
+            server.abort();
+        }
+    }
+    if let Some(replicas) = &replicas {
+        replicas.close();
+    }
+    hub.close();
+    conn.close();
+    tracing::info!("closed Redis connections");
+    if let Some(provider) = tracer_provider {
+        let _ = provider.shutdown();
+    }
 }
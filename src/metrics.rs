@@ -0,0 +1,292 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::collections::HashSet;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+/// Process-wide metrics, exported in Prometheus text format on `/metrics`
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub pipeline_size: Histogram,
+    pub redis_duration: HistogramVec,
+    pub redis_timeouts: IntCounter,
+    pub redis_connection_errors: IntCounter,
+    pub sse_subscriptions: IntGauge,
+    pub sse_channels: IntGauge,
+    pub websocket_sessions: IntGauge,
+    pub pubsub_topics: IntGauge,
+    pub pubsub_lagged: IntCounter,
+    pub pubsub_reconnects: IntCounter,
+    pub auth_failures: IntCounter,
+    pub policy_denials: IntCounterVec,
+    pub config_reloads: IntCounterVec,
+    /// Lowercase command names from the command table, the only values
+    /// `command_label` passes through
+    command_names: RwLock<HashSet<String>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let latency = exponential_buckets(0.0005, 2.0, 16).expect("valid buckets");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("sr_http_requests_total", "HTTP requests handled"),
+            &["route", "command", "status"],
+        )
+        .expect("valid metric");
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("sr_http_request_duration_seconds", "HTTP request latency")
+                .buckets(latency.clone()),
+            &["route", "command", "status"],
+        )
+        .expect("valid metric");
+        let pipeline_size = Histogram::with_opts(
+            HistogramOpts::new("sr_pipeline_commands", "Commands per pipeline request")
+                .buckets(exponential_buckets(1.0, 2.0, 12).expect("valid buckets")),
+        )
+        .expect("valid metric");
+        let redis_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "sr_redis_roundtrip_seconds",
+                "Redis round-trip latency per command or pipeline",
+            )
+            .buckets(latency),
+            &["command"],
+        )
+        .expect("valid metric");
+        let redis_timeouts = IntCounter::new(
+            "sr_redis_timeouts_total",
+            "Redis calls that exceeded their deadline",
+        )
+        .expect("valid metric");
+        let redis_connection_errors = IntCounter::new(
+            "sr_redis_connection_errors_total",
+            "Redis calls that failed because the connection was unavailable",
+        )
+        .expect("valid metric");
+        let sse_subscriptions = IntGauge::new(
+            "sr_sse_subscriptions_active",
+            "Open SSE subscribe/psubscribe streams",
+        )
+        .expect("valid metric");
+        let sse_channels = IntGauge::new(
+            "sr_sse_channels_active",
+            "Channels and patterns held by open SSE streams",
+        )
+        .expect("valid metric");
+        let websocket_sessions = IntGauge::new("sr_websocket_sessions_active", "Open /ws sessions")
+            .expect("valid metric");
+        let pubsub_topics = IntGauge::new(
+            "sr_pubsub_topics_active",
+            "Channels and patterns subscribed on Redis, shared by all subscribers",
+        )
+        .expect("valid metric");
+        let pubsub_lagged = IntCounter::new(
+            "sr_pubsub_lagged_messages_total",
+            "Messages skipped by subscribers that fell behind",
+        )
+        .expect("valid metric");
+        let pubsub_reconnects = IntCounter::new(
+            "sr_pubsub_reconnects_total",
+            "Subscriber connections to Redis lost and re-established",
+        )
+        .expect("valid metric");
+        let auth_failures = IntCounter::new(
+            "sr_auth_failures_total",
+            "Requests rejected by bearer-token validation",
+        )
+        .expect("valid metric");
+        let policy_denials = IntCounterVec::new(
+            Opts::new(
+                "sr_policy_denials_total",
+                "Requests refused by command policy or rate limit",
+            ),
+            &["reason"],
+        )
+        .expect("valid metric");
+        let config_reloads = IntCounterVec::new(
+            Opts::new("sr_config_reloads_total", "Configuration reload attempts"),
+            &["result"],
+        )
+        .expect("valid metric");
+
+        registry
+            .register(Box::new(http_requests.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(http_duration.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(pipeline_size.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(redis_duration.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(redis_timeouts.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(redis_connection_errors.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(sse_subscriptions.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(sse_channels.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(websocket_sessions.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(pubsub_topics.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(pubsub_lagged.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(pubsub_reconnects.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(auth_failures.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(policy_denials.clone()))
+            .expect("unique metric");
+        registry
+            .register(Box::new(config_reloads.clone()))
+            .expect("unique metric");
+
+        Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            pipeline_size,
+            redis_duration,
+            redis_timeouts,
+            redis_connection_errors,
+            sse_subscriptions,
+            sse_channels,
+            websocket_sessions,
+            pubsub_topics,
+            pubsub_lagged,
+            pubsub_reconnects,
+            auth_failures,
+            policy_denials,
+            config_reloads,
+            command_names: RwLock::default(),
+        }
+    }
+
+    /// Replace the command names `command_label` accepts
+    pub fn set_command_names(&self, names: HashSet<String>) {
+        *self.command_names.write().unwrap() = names;
+    }
+
+    /// Record one finished HTTP request
+    pub fn observe_request(&self, route: &str, command: &str, status: StatusCode, took: Duration) {
+        let status = status.as_str();
+        let labels = [route, command, status];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_duration
+            .with_label_values(&labels)
+            .observe(took.as_secs_f64());
+    }
+
+    /// Record the outcome of one Redis round trip
+    pub fn observe_redis<T>(&self, command: &str, took: Duration, res: &anyhow::Result<T>) {
+        self.redis_duration
+            .with_label_values(&[command])
+            .observe(took.as_secs_f64());
+        if let Err(e) = res {
+            if e.is::<tokio::time::error::Elapsed>() {
+                self.redis_timeouts.inc();
+            } else if crate::upstream::is_unavailable(e) {
+                self.redis_connection_errors.inc();
+            }
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("text encoding never fails");
+        String::from_utf8(buf).expect("text format is UTF-8")
+    }
+}
+
+/// Normalise a client-supplied command name into a bounded label value:
+/// commands Redis reported in `COMMAND` keep their name, anything else
+/// (including everything before the table loads) is `unknown`
+pub fn command_label(name: Option<&str>) -> String {
+    let Some(name) = name else {
+        return "".into();
+    };
+    let name = name.to_ascii_lowercase();
+    if METRICS.command_names.read().unwrap().contains(&name) {
+        name
+    } else {
+        "unknown".into()
+    }
+}
+
+/// Decrements the SSE gauges when a subscribe stream is dropped
+pub struct SseGuard {
+    channels: i64,
+}
+
+impl SseGuard {
+    pub fn new(channels: usize) -> Self {
+        let channels = channels as i64;
+        METRICS.sse_subscriptions.inc();
+        METRICS.sse_channels.add(channels);
+        SseGuard { channels }
+    }
+}
+
+impl Drop for SseGuard {
+    fn drop(&mut self) {
+        METRICS.sse_subscriptions.dec();
+        METRICS.sse_channels.sub(self.channels);
+    }
+}
+
+/// Decrements the WebSocket gauge when a `/ws` session ends
+pub struct WsGuard;
+
+impl WsGuard {
+    pub fn new() -> Self {
+        METRICS.websocket_sessions.inc();
+        WsGuard
+    }
+}
+
+impl Default for WsGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for WsGuard {
+    fn drop(&mut self) {
+        METRICS.websocket_sessions.dec();
+    }
+}
+
+pub async fn get_metrics() -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+        .into_response()
+}
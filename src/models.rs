@@ -1,10 +1,26 @@
-use redis::aio::ConnectionManager;
+use crate::commands::CommandTable;
+use crate::config::Config;
+use crate::hub::Hub;
+use crate::policy::AccessControl;
+use crate::replicas::ReplicaSet;
+use crate::shutdown::Shutdown;
+use crate::upstream::Upstream;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub conn: ConnectionManager,
+    pub conn: Upstream,
     pub redis_url: String,
+    pub commands: Arc<CommandTable>,
+    pub replicas: Option<Arc<ReplicaSet>>,
+    /// Settings fixed at startup. Auth settings live in `access` instead,
+    /// which is swapped on reload.
+    pub config: Arc<Config>,
+    pub access: Arc<AccessControl>,
+    pub shutdown: Shutdown,
+    /// Shared subscriber connections for SSE and WebSocket clients
+    pub hub: Hub,
 }
 
 #[derive(Serialize, Deserialize)]
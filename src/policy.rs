@@ -0,0 +1,639 @@
+use crate::commands::CommandTable;
+use crate::config::{Cli, Config, DurableConfig, RateLimitConfig, TokenConfig};
+use crate::glob::Glob;
+use crate::metrics::METRICS;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// An authenticated caller and what it may do
+pub struct Principal {
+    pub name: String,
+    token: String,
+    read_only: bool,
+    allow_commands: HashSet<String>,
+    deny_commands: HashSet<String>,
+    key_prefixes: Vec<String>,
+    subscribe_channels: Vec<Glob>,
+    publish_channels: Vec<Glob>,
+    rate_limit: Option<RateLimitConfig>,
+}
+
+/// Where Redis publishes keyspace notifications
+const NOTIFICATION_CHANNELS: [&str; 2] = ["__keyspace@", "__keyevent@"];
+
+fn command_set(names: &[String]) -> HashSet<String> {
+    names.iter().map(|n| n.to_ascii_lowercase()).collect()
+}
+
+impl Principal {
+    fn new(t: &TokenConfig, defaults: Option<RateLimitConfig>) -> Self {
+        Principal {
+            name: t.name.clone(),
+            token: t.token.clone(),
+            read_only: t.read_only,
+            allow_commands: command_set(&t.allow_commands),
+            deny_commands: command_set(&t.deny_commands),
+            key_prefixes: t.key_prefixes.clone(),
+            subscribe_channels: t.subscribe_channels.iter().map(|g| Glob::new(g)).collect(),
+            publish_channels: t.publish_channels.iter().map(|g| Glob::new(g)).collect(),
+            rate_limit: t.rate_limit.or(defaults),
+        }
+    }
+
+    /// The first channel or pattern in a pub/sub command that the token's
+    /// channel globs don't grant
+    fn denied_channel<'a>(&self, name: &str, cmd: &'a [String]) -> Option<&'a str> {
+        let (grants, args) = match name {
+            "subscribe" | "ssubscribe" | "psubscribe" => (&self.subscribe_channels, &cmd[1..]),
+            "publish" | "spublish" => (&self.publish_channels, &cmd[1..cmd.len().min(2)]),
+            _ => return None,
+        };
+        if grants.is_empty() {
+            return None;
+        }
+        args.iter()
+            .find(|arg| {
+                let granted = if name == "psubscribe" {
+                    let pattern = Glob::new(arg);
+                    grants.iter().any(|g| g.covers(&pattern))
+                } else {
+                    grants.iter().any(|g| g.matches(arg))
+                };
+                !granted
+            })
+            .map(String::as_str)
+    }
+
+    /// The first channel or pattern in a subscribe command that could carry
+    /// keyspace notifications, if the token is limited to key prefixes:
+    /// notifications name keys the token may not see
+    fn denied_notifications<'a>(&self, cmd: &'a [String]) -> Option<&'a str> {
+        if self.key_prefixes.is_empty() {
+            return None;
+        }
+        let name = cmd.first()?.to_ascii_lowercase();
+        cmd[1..]
+            .iter()
+            .find(|arg| match name.as_str() {
+                "subscribe" | "ssubscribe" => {
+                    NOTIFICATION_CHANNELS.iter().any(|p| arg.starts_with(p))
+                }
+                "psubscribe" => {
+                    let pattern = Glob::new(arg);
+                    NOTIFICATION_CHANNELS
+                        .iter()
+                        .any(|p| pattern.matches_prefix(p))
+                }
+                _ => false,
+            })
+            .map(String::as_str)
+    }
+
+    /// Whether `key` falls under the token's key prefixes, if it has any
+    pub fn allows_key(&self, key: &str) -> bool {
+        self.key_prefixes.is_empty() || self.key_prefixes.iter().any(|p| key.starts_with(p))
+    }
+}
+
+/// One snapshot of the reloadable auth settings
+struct Policy {
+    tokens: Vec<Arc<Principal>>,
+    /// Used for every request when no tokens are configured
+    anonymous: Arc<Principal>,
+    deny_commands: HashSet<String>,
+}
+
+impl Policy {
+    fn from_config(config: &Config) -> Self {
+        let defaults = config.policy.rate_limit;
+        Policy {
+            tokens: config
+                .auth
+                .all_tokens()
+                .iter()
+                .map(|t| Arc::new(Principal::new(t, defaults)))
+                .collect(),
+            anonymous: Arc::new(Principal::new(
+                &TokenConfig {
+                    name: "anonymous".into(),
+                    ..Default::default()
+                },
+                defaults,
+            )),
+            deny_commands: command_set(&config.policy.deny_commands),
+        }
+    }
+}
+
+/// Why a request was refused
+#[derive(Debug)]
+pub enum Denied {
+    Command(String),
+    ReadOnly(String),
+    Key { command: String, key: String },
+    UncheckableKeys(String),
+    Channel { command: String, channel: String },
+    RateLimited(Duration),
+}
+
+impl Denied {
+    /// Label for `sr_policy_denials_total`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Denied::Command(_) => "command",
+            Denied::ReadOnly(_) => "read_only",
+            Denied::Key { .. } | Denied::UncheckableKeys(_) => "key",
+            Denied::Channel { .. } => "channel",
+            Denied::RateLimited(_) => "rate_limit",
+        }
+    }
+}
+
+impl std::fmt::Display for Denied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denied::Command(c) => write!(f, "Command {} is not allowed for this token.", c),
+            Denied::ReadOnly(c) => write!(f, "Command {} is not allowed for a read-only token.", c),
+            Denied::Key { command, key } => {
+                write!(f, "Key {:?} in {} is outside this token's key prefixes.", key, command)
+            }
+            Denied::UncheckableKeys(c) => write!(
+                f,
+                "Command {} is not allowed for a token with key prefixes: its keys can't be determined.",
+                c
+            ),
+            Denied::Channel { command, channel } => write!(
+                f,
+                "Channel {:?} in {} is outside this token's channels.",
+                channel, command
+            ),
+            Denied::RateLimited(_) => write!(f, "Rate limit exceeded. Try again later."),
+        }
+    }
+}
+
+struct Bucket {
+    limit: RateLimitConfig,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimitConfig) -> Self {
+        Bucket {
+            limit,
+            tokens: limit.burst(),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take `n` tokens, or report how long until that many are available
+    fn take(&mut self, n: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let burst = self.limit.burst();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(burst);
+        self.updated = now;
+        // A request bigger than the bucket drains it rather than never fitting
+        let n = n.min(burst);
+        if self.tokens >= n {
+            self.tokens -= n;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (n - self.tokens) / self.limit.per_second,
+            ))
+        }
+    }
+}
+
+/// Tokens, permissions and rate limits, swapped atomically on reload.
+///
+/// Requests resolve their `Principal` once, at authentication, so a reload
+/// never changes the rules under a request or an open stream. `/ws`
+/// sessions, which can run commands for hours, resolve it again per frame.
+pub struct AccessControl {
+    policy: RwLock<Arc<Policy>>,
+    /// Keyed by principal name so limits carry over across reloads
+    buckets: Mutex<HashMap<String, Bucket>>,
+    /// Durable mode settings, if it's on. Fixed at startup like the mode
+    /// itself, since a reload can't turn it on or off.
+    durable: Option<DurableConfig>,
+}
+
+impl AccessControl {
+    pub fn new(config: &Config) -> Self {
+        AccessControl {
+            policy: RwLock::new(Arc::new(Policy::from_config(config))),
+            buckets: Mutex::new(HashMap::new()),
+            durable: config.durable.enabled.then(|| config.durable.clone()),
+        }
+    }
+
+    /// Replace the tokens and policy with those from `config`
+    pub fn reload(&self, config: &Config) {
+        let policy = Arc::new(Policy::from_config(config));
+        let names: HashSet<&str> = policy.tokens.iter().map(|p| p.name.as_str()).collect();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|name, _| name == "anonymous" || names.contains(name.as_str()));
+        *self.policy.write().unwrap() = policy;
+    }
+
+    fn current(&self) -> Arc<Policy> {
+        self.policy.read().unwrap().clone()
+    }
+
+    /// Whether any tokens are configured
+    pub fn auth_enabled(&self) -> bool {
+        !self.current().tokens.is_empty()
+    }
+
+    /// Resolve the caller. `None` means the request must be rejected; with no
+    /// tokens configured everyone is `anonymous`.
+    pub fn authenticate(&self, token: Option<&str>) -> Option<Arc<Principal>> {
+        let policy = self.current();
+        if policy.tokens.is_empty() {
+            return Some(policy.anonymous.clone());
+        }
+        let token = token?;
+        // Compare against every token so timing doesn't reveal which matched
+        let mut matched = None;
+        for p in &policy.tokens {
+            if constant_time_eq(token.as_bytes(), p.token.as_bytes()) {
+                matched = Some(p.clone());
+            }
+        }
+        matched
+    }
+
+    /// Check `cmds` against the global and per-token rules, then charge them
+    /// to the principal's rate limit
+    pub fn check(
+        &self,
+        principal: &Principal,
+        table: &CommandTable,
+        cmds: &[Vec<String>],
+    ) -> Result<(), Denied> {
+        let res = self.check_inner(principal, table, cmds);
+        if let Err(d) = &res {
+            METRICS
+                .policy_denials
+                .with_label_values(&[d.reason()])
+                .inc();
+        }
+        res
+    }
+
+    /// `check`, and also keep tokens limited to key prefixes from subscribing
+    /// to keyspace notifications. For every route but `/keyspace` and
+    /// `/keyevent`, which filter notifications by key instead.
+    pub fn check_subscribe(
+        &self,
+        principal: &Principal,
+        table: &CommandTable,
+        cmds: &[Vec<String>],
+    ) -> Result<(), Denied> {
+        for cmd in cmds {
+            if let Some(channel) = principal.denied_notifications(cmd) {
+                let denied = Denied::Channel {
+                    command: cmd[0].to_ascii_uppercase(),
+                    channel: channel.to_string(),
+                };
+                METRICS
+                    .policy_denials
+                    .with_label_values(&[denied.reason()])
+                    .inc();
+                return Err(denied);
+            }
+        }
+        self.check(principal, table, cmds)
+    }
+
+    fn check_inner(
+        &self,
+        principal: &Principal,
+        table: &CommandTable,
+        cmds: &[Vec<String>],
+    ) -> Result<(), Denied> {
+        let policy = self.current();
+        for cmd in cmds {
+            let Some(name) = cmd.first() else {
+                continue;
+            };
+            let lower = name.to_ascii_lowercase();
+            let display = name.to_ascii_uppercase();
+            if policy.deny_commands.contains(&lower)
+                || principal.deny_commands.contains(&lower)
+                || (!principal.allow_commands.is_empty()
+                    && !principal.allow_commands.contains(&lower))
+            {
+                return Err(Denied::Command(display));
+            }
+            if principal.read_only && !table.is_read_only(cmd) && !is_subscribe(&lower) {
+                return Err(Denied::ReadOnly(display));
+            }
+            // EVAL_RO and friends go out as the base command, which can write
+            if principal.read_only && crate::redis_client::ro_fallback(&lower).is_some() {
+                return Err(Denied::ReadOnly(display));
+            }
+            if !principal.key_prefixes.is_empty() {
+                let Some(keys) = table.keys(cmd) else {
+                    return Err(Denied::UncheckableKeys(display));
+                };
+                for key in keys {
+                    if !principal.allows_key(key) {
+                        return Err(Denied::Key {
+                            command: display,
+                            key: key.to_string(),
+                        });
+                    }
+                }
+                // A durable PUBLISH also appends to the channel's stream
+                let durable = self.durable.as_ref().filter(|_| lower == "publish");
+                if let (Some(durable), Some(channel)) = (durable, cmd.get(1)) {
+                    let key = crate::durable::stream_key(durable, channel);
+                    if !principal.allows_key(&key) {
+                        return Err(Denied::Key {
+                            command: display,
+                            key,
+                        });
+                    }
+                }
+            }
+            if let Some(channel) = principal.denied_channel(&lower, cmd) {
+                return Err(Denied::Channel {
+                    command: display,
+                    channel: channel.to_string(),
+                });
+            }
+        }
+        if let Some(limit) = principal.rate_limit {
+            let mut buckets = self.buckets.lock().unwrap();
+            let bucket = buckets
+                .entry(principal.name.clone())
+                .or_insert_with(|| Bucket::new(limit));
+            if bucket.limit != limit {
+                *bucket = Bucket::new(limit);
+            }
+            bucket
+                .take(cmds.len().max(1) as f64)
+                .map_err(Denied::RateLimited)?;
+        }
+        Ok(())
+    }
+}
+
+/// Subscribing is allowed for read-only tokens even though Redis doesn't
+/// flag it `readonly`
+fn is_subscribe(cmd: &str) -> bool {
+    matches!(cmd, "subscribe" | "psubscribe" | "ssubscribe")
+}
+
+/// Constant-time comparison of two byte slices to prevent timing attacks
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| x ^ y)
+        .fold(0u8, |acc, diff| acc | diff)
+        == 0
+}
+
+/// Reload the configuration and apply its auth settings. A config that fails
+/// to load or validate is logged and the current one stays in place.
+pub fn reload(access: &AccessControl, cli: &Cli, running: &Config) {
+    match Config::load(cli) {
+        Ok(config) => {
+            access.reload(&config);
+            METRICS.config_reloads.with_label_values(&["ok"]).inc();
+            tracing::info!(
+                tokens = config.auth.all_tokens().len(),
+                deny_commands = config.policy.deny_commands.len(),
+                "reloaded tokens and policy"
+            );
+            let ignored = running.restart_required(&config);
+            if !ignored.is_empty() {
+                tracing::warn!(
+                    sections = ignored.join(", "),
+                    "changed settings need a restart to take effect"
+                );
+            }
+        }
+        Err(e) => {
+            METRICS.config_reloads.with_label_values(&["error"]).inc();
+            tracing::error!(
+                error = format!("{:#}", e),
+                "reload failed; keeping current policy"
+            );
+        }
+    }
+}
+
+fn file_stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Reload on SIGHUP and, when `reload.watch` is set, whenever the config file
+/// or a token file changes
+pub fn spawn_reloader(access: Arc<AccessControl>, cli: Cli, running: Arc<Config>) {
+    let files = if running.reload.watch {
+        Config::watched_files(&cli).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let interval = Duration::from_millis(running.reload.interval_ms);
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+        let mut stamps: Vec<_> = files.iter().map(file_stamp).collect();
+        let mut tick = tokio::time::interval(interval);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            #[cfg(unix)]
+            let hup = async {
+                match hangup.as_mut() {
+                    Some(h) => {
+                        h.recv().await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hup = std::future::pending::<()>();
+            tokio::select! {
+                _ = hup => {
+                    tracing::info!("SIGHUP received, reloading");
+                    reload(&access, &cli, &running);
+                    // Already picked up whatever changed on disk
+                    stamps = files.iter().map(file_stamp).collect();
+                }
+                _ = tick.tick(), if !files.is_empty() => {
+                    let now: Vec<_> = files.iter().map(file_stamp).collect();
+                    if now != stamps {
+                        stamps = now;
+                        tracing::info!("config file changed, reloading");
+                        reload(&access, &cli, &running);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+
+    /// A `COMMAND` entry for a keyless command
+    fn entry(name: &str, flags: &[&str]) -> Value {
+        Value::Array(vec![
+            Value::BulkString(name.into()),
+            Value::Int(-3),
+            Value::Array(
+                flags
+                    .iter()
+                    .map(|f| Value::SimpleString(f.to_string()))
+                    .collect(),
+            ),
+            Value::Int(0),
+            Value::Int(0),
+            Value::Int(0),
+        ])
+    }
+
+    fn cmd(args: &[&str]) -> Vec<Vec<String>> {
+        vec![args.iter().map(|a| a.to_string()).collect()]
+    }
+
+    #[test]
+    fn read_only_tokens_cannot_run_rewritten_script_variants() {
+        let table = CommandTable::from_entries(vec![
+            entry("eval_ro", &["readonly", "noscript"]),
+            entry("evalsha_ro", &["readonly", "noscript"]),
+            entry("fcall_ro", &["readonly", "noscript"]),
+            entry("get", &["readonly"]),
+        ]);
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.tokens]]
+            name = "read-only"
+            token = "ro"
+            read_only = true
+
+            [[auth.tokens]]
+            name = "full"
+            token = "rw"
+            "#,
+        )
+        .unwrap();
+        let access = AccessControl::new(&config);
+        let read_only = access.authenticate(Some("ro")).unwrap();
+        let full = access.authenticate(Some("rw")).unwrap();
+
+        for variant in ["EVAL_RO", "evalsha_ro", "FCALL_RO"] {
+            let cmds = cmd(&[variant, "return 1", "0"]);
+            assert!(
+                matches!(
+                    access.check(&read_only, &table, &cmds),
+                    Err(Denied::ReadOnly(_))
+                ),
+                "{}",
+                variant
+            );
+            access.check(&full, &table, &cmds).unwrap();
+        }
+        access
+            .check(&read_only, &table, &cmd(&["GET", "k"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn durable_publish_checks_the_stream_key() {
+        let table = CommandTable::from_entries(vec![
+            entry("publish", &["pubsub"]),
+            entry("spublish", &["pubsub"]),
+        ]);
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.tokens]]
+            name = "prefixed"
+            token = "app"
+            key_prefixes = ["app:"]
+
+            [durable]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        let access = AccessControl::new(&config);
+        let prefixed = access.authenticate(Some("app")).unwrap();
+
+        // Recorded under sr:durable:news, outside the token's prefix
+        assert!(matches!(
+            access.check(&prefixed, &table, &cmd(&["PUBLISH", "news", "hi"])),
+            Err(Denied::Key { key, .. }) if key == "sr:durable:news"
+        ));
+        // Shard channels aren't recorded
+        access
+            .check(&prefixed, &table, &cmd(&["SPUBLISH", "news", "hi"]))
+            .unwrap();
+
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.tokens]]
+            name = "prefixed"
+            token = "app"
+            key_prefixes = ["app:"]
+            "#,
+        )
+        .unwrap();
+        let access = AccessControl::new(&config);
+        let prefixed = access.authenticate(Some("app")).unwrap();
+        access
+            .check(&prefixed, &table, &cmd(&["PUBLISH", "news", "hi"]))
+            .unwrap();
+    }
+
+    #[test]
+    fn prefixed_tokens_cannot_subscribe_to_notifications() {
+        let table = CommandTable::from_entries(vec![
+            entry("subscribe", &["pubsub"]),
+            entry("psubscribe", &["pubsub"]),
+            entry("ssubscribe", &["pubsub"]),
+        ]);
+        let config: Config = toml::from_str(
+            r#"
+            [[auth.tokens]]
+            name = "prefixed"
+            token = "app"
+            key_prefixes = ["app:"]
+            "#,
+        )
+        .unwrap();
+        let access = AccessControl::new(&config);
+        let prefixed = access.authenticate(Some("app")).unwrap();
+
+        for cmds in [
+            cmd(&["SUBSCRIBE", "__keyevent@0__:set"]),
+            cmd(&["PSUBSCRIBE", "__key*"]),
+            cmd(&["SSUBSCRIBE", "__keyspace@0__:other:key"]),
+        ] {
+            assert!(matches!(
+                access.check_subscribe(&prefixed, &table, &cmds),
+                Err(Denied::Channel { .. })
+            ));
+        }
+        access
+            .check_subscribe(&prefixed, &table, &cmd(&["SSUBSCRIBE", "app-news"]))
+            .unwrap();
+    }
+}
@@ -1,4 +1,9 @@
-use redis::{aio::PubSub, Client, Msg};
+use axum::response::sse::Event;
+use redis::aio::{MultiplexedConnection, PubSub};
+use redis::{AsyncConnectionConfig, Client, IntoConnectionInfo, ProtocolVersion, PushInfo};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 
 /// Creates a dedicated Pub/Sub connection
 pub async fn create_pubsub_connection(redis_url: &str) -> anyhow::Result<PubSub> {
@@ -7,48 +12,265 @@ pub async fn create_pubsub_connection(redis_url: &str) -> anyhow::Result<PubSub>
     Ok(pubsub)
 }
 
-/// Subscribe to multiple channels
-pub async fn subscribe_to_channels(
-    pubsub: &mut PubSub,
-    channels: &[String],
-) -> anyhow::Result<usize> {
-    for channel in channels {
-        pubsub.subscribe(channel).await?;
+/// Creates a dedicated RESP3 connection for sharded Pub/Sub, whose messages
+/// arrive on the returned receiver. The RESP2 `PubSub` client can't send
+/// `SSUBSCRIBE`.
+pub async fn create_sharded_connection(
+    redis_url: &str,
+) -> anyhow::Result<(MultiplexedConnection, UnboundedReceiver<PushInfo>)> {
+    let info = redis_url.into_connection_info()?;
+    let settings = info
+        .redis_settings()
+        .clone()
+        .set_protocol(ProtocolVersion::RESP3);
+    let client = Client::open(info.set_redis_settings(settings))?;
+    let (tx, rx) = unbounded_channel();
+    let config = AsyncConnectionConfig::new().set_push_sender(tx);
+    let conn = client
+        .get_multiplexed_async_connection_with_config(&config)
+        .await?;
+    Ok((conn, rx))
+}
+
+/// Message types for Pub/Sub
+#[derive(Debug)]
+pub enum PubSubMessage {
+    Message {
+        channel: String,
+        /// The payload as text, or base64-encoded if `base64` is set
+        payload: String,
+        base64: bool,
+    },
+    PMessage {
+        pattern: String,
+        channel: String,
+        payload: String,
+        base64: bool,
+    },
+    /// A message on a shard channel
+    SMessage {
+        channel: String,
+        payload: String,
+        base64: bool,
+    },
+    Subscribe {
+        channel: String,
+        count: usize,
+    },
+    Unsubscribe {
+        channel: String,
+        count: usize,
+    },
+    PSubscribe {
+        pattern: String,
+        count: usize,
+    },
+    PUnsubscribe {
+        pattern: String,
+        count: usize,
+    },
+    SSubscribe {
+        channel: String,
+        count: usize,
+    },
+}
+
+impl PubSubMessage {
+    /// The Redis message type, e.g. `message` or `psubscribe`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PubSubMessage::Message { .. } => "message",
+            PubSubMessage::PMessage { .. } => "pmessage",
+            PubSubMessage::SMessage { .. } => "smessage",
+            PubSubMessage::Subscribe { .. } => "subscribe",
+            PubSubMessage::Unsubscribe { .. } => "unsubscribe",
+            PubSubMessage::PSubscribe { .. } => "psubscribe",
+            PubSubMessage::PUnsubscribe { .. } => "punsubscribe",
+            PubSubMessage::SSubscribe { .. } => "ssubscribe",
+        }
+    }
+
+    /// The message as a JSON object with a `type` field. The payload is
+    /// always a string, so `hello`, `"hello"` and `123` stay distinct; base64
+    /// payloads are flagged with `"encoding": "base64"`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let kind = self.kind();
+        let flag = |mut v: serde_json::Value, base64: bool| {
+            if base64 {
+                v["encoding"] = json!("base64");
+            }
+            v
+        };
+        match self {
+            PubSubMessage::Message {
+                channel,
+                payload: p,
+                base64,
+            }
+            | PubSubMessage::SMessage {
+                channel,
+                payload: p,
+                base64,
+            } => flag(
+                json!({"type": kind, "channel": channel, "payload": p}),
+                *base64,
+            ),
+            PubSubMessage::PMessage {
+                pattern,
+                channel,
+                payload: p,
+                base64,
+            } => flag(
+                json!({"type": kind, "pattern": pattern, "channel": channel, "payload": p}),
+                *base64,
+            ),
+            PubSubMessage::Subscribe { channel, count }
+            | PubSubMessage::Unsubscribe { channel, count }
+            | PubSubMessage::SSubscribe { channel, count } => {
+                json!({"type": kind, "channel": channel, "count": count})
+            }
+            PubSubMessage::PSubscribe { pattern, count }
+            | PubSubMessage::PUnsubscribe { pattern, count } => {
+                json!({"type": kind, "pattern": pattern, "count": count})
+            }
+        }
     }
-    Ok(channels.len())
 }
 
-/// Subscribe to multiple patterns
-pub async fn psubscribe_to_patterns(
-    pubsub: &mut PubSub,
-    patterns: &[String],
-) -> anyhow::Result<usize> {
-    for pattern in patterns {
-        pubsub.psubscribe(pattern).await?;
+/// How SSE streams encode events, chosen with `?format=`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SseFormat {
+    /// `data: message,<channel>,<payload>`, as Upstash sends it
+    #[default]
+    Upstash,
+    /// `event: <type>` with a JSON object as data
+    Json,
+}
+
+/// How message payloads are sent, from `upstash-encoding` or `?encoding=`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    /// Text as-is; payloads that aren't UTF-8 as flagged base64
+    #[default]
+    Text,
+    /// Every payload as base64
+    Base64,
+}
+
+/// Query parameters of the SSE routes
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct StreamParams {
+    pub format: SseFormat,
+    pub encoding: PayloadEncoding,
+    /// Resume from `Last-Event-ID` using the recorded message history
+    pub durable: bool,
+}
+
+/// Body of `POST /subscribe`, naming channels and patterns to follow in
+/// one stream
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SubscribeBody {
+    pub channels: Vec<String>,
+    pub patterns: Vec<String>,
+}
+
+/// Query parameters of `POST /publish`
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PublishParams {
+    /// Publish to shard channels with `SPUBLISH`
+    pub sharded: bool,
+}
+
+/// A Pub/Sub message as an SSE event
+pub fn sse_event(msg: &PubSubMessage, format: SseFormat) -> Event {
+    match format {
+        // Unnamed, so `EventSource.onmessage` and Upstash clients see it.
+        // Only the JSON format can flag base64 payloads.
+        SseFormat::Upstash => Event::default().data(format_sse_message(msg)),
+        SseFormat::Json => Event::default()
+            .event(msg.kind())
+            .data(msg.to_json().to_string()),
     }
-    Ok(patterns.len())
 }
 
-/// Message types for Pub/Sub
-#[derive(Debug)]
-pub enum PubSubMessage {
-    Message { channel: String, payload: String },
-    PMessage { pattern: String, channel: String, payload: String },
-    Subscribe { channel: String, count: usize },
-    Unsubscribe { channel: String, count: usize },
-    PSubscribe { pattern: String, count: usize },
-    PUnsubscribe { pattern: String, count: usize },
+/// A named event about the stream itself rather than a message
+fn notice(name: &'static str, format: SseFormat) -> Event {
+    let data = match format {
+        SseFormat::Upstash => name.to_string(),
+        SseFormat::Json => json!({ "type": name }).to_string(),
+    };
+    Event::default().event(name).data(data)
+}
+
+/// Final event sent to subscribers when the server shuts down. Clients
+/// should reconnect, ideally to another instance.
+pub fn reconnect_event(format: SseFormat) -> Event {
+    notice("reconnect", format).retry(crate::shutdown::RECONNECT_AFTER)
+}
+
+/// Sent when the subscriber connection to Redis drops. Messages published
+/// until `resubscribed` are missed.
+pub fn reconnecting_event(format: SseFormat) -> Event {
+    notice("reconnecting", format)
+}
+
+/// Sent once the server has reconnected and subscribed again
+pub fn resubscribed_event(format: SseFormat) -> Event {
+    notice("resubscribed", format)
+}
+
+/// Sent when the client read too slowly and `missed` messages were dropped
+pub fn lagged_event(format: SseFormat, missed: u64) -> Event {
+    let data = match format {
+        SseFormat::Upstash => format!("lagged,{}", missed),
+        SseFormat::Json => json!({ "type": "lagged", "missed": missed }).to_string(),
+    };
+    Event::default().event("lagged").data(data)
 }
 
 /// Format a Pub/Sub message into SSE format
 /// Format: "<type>,<fields>" (without "data: " prefix as that's added by SSE Event)
 pub fn format_sse_message(msg: &PubSubMessage) -> String {
     match msg {
-        PubSubMessage::Message { channel, payload } => {
-            format!("message,{},{}", channel, payload)
+        PubSubMessage::Message {
+            channel,
+            payload,
+            base64,
+        } => {
+            format!(
+                "message,{},{}",
+                channel,
+                payload_to_json_string(payload, *base64)
+            )
         }
-        PubSubMessage::PMessage { pattern, channel, payload } => {
-            format!("pmessage,{},{},{}", pattern, channel, payload)
+        PubSubMessage::PMessage {
+            pattern,
+            channel,
+            payload,
+            base64,
+        } => {
+            format!(
+                "pmessage,{},{},{}",
+                pattern,
+                channel,
+                payload_to_json_string(payload, *base64)
+            )
+        }
+        PubSubMessage::SMessage {
+            channel,
+            payload,
+            base64,
+        } => {
+            format!(
+                "smessage,{},{}",
+                channel,
+                payload_to_json_string(payload, *base64)
+            )
         }
         PubSubMessage::Subscribe { channel, count } => {
             format!("subscribe,{},{}", channel, count)
@@ -62,49 +284,82 @@ pub fn format_sse_message(msg: &PubSubMessage) -> String {
         PubSubMessage::PUnsubscribe { pattern, count } => {
             format!("punsubscribe,{},{}", pattern, count)
         }
+        PubSubMessage::SSubscribe { channel, count } => {
+            format!("ssubscribe,{},{}", channel, count)
+        }
     }
 }
 
-/// Convert Redis message payload to JSON string
-pub fn payload_to_json_string(payload: &[u8]) -> String {
+/// A payload as text for `PubSubMessage`, and whether it was base64-encoded:
+/// always with `base64`, otherwise only if it isn't UTF-8
+pub fn encode_payload(payload: &[u8], base64: bool) -> (String, bool) {
     match std::str::from_utf8(payload) {
-        Ok(s) => {
-            // Attempt to parse as JSON first - this validates it's actually valid JSON
-            // If parsing succeeds, return the original string (it's already valid JSON)
-            // This preserves JSON objects/arrays without double-wrapping them
-            match serde_json::from_str::<serde_json::Value>(s) {
-                Ok(_) => s.to_string(),
-                Err(_) => {
-                    // Not valid JSON, wrap it as a JSON string
-                    serde_json::to_string(s).unwrap_or_else(|_| format!("\"{}\"", s))
-                }
-            }
-        }
-        Err(_) => {
-            // If not valid UTF-8, encode as base64 and wrap as JSON string
+        Ok(text) if !base64 => (text.to_string(), false),
+        _ => {
             use base64::engine::general_purpose::STANDARD;
             use base64::Engine;
-            let encoded = STANDARD.encode(payload);
-            serde_json::to_string(&encoded).unwrap_or_else(|_| format!("\"{}\"", encoded))
+            (STANDARD.encode(payload), true)
         }
     }
 }
 
-/// Parse Redis Pub/Sub message into our PubSubMessage type
-pub fn parse_redis_message(msg: &Msg) -> Option<PubSubMessage> {
-    let channel = msg.get_channel_name().to_string();
-    let payload_bytes: Vec<u8> = msg.get_payload().ok()?;
-    let payload = payload_to_json_string(&payload_bytes);
+/// A payload as JSON for the Upstash format: text that is valid JSON as-is,
+/// so objects and arrays aren't double-wrapped, anything else as a JSON string
+fn payload_to_json_string(payload: &str, base64: bool) -> String {
+    if !base64 && serde_json::from_str::<serde_json::Value>(payload).is_ok() {
+        return payload.to_string();
+    }
+    serde_json::to_string(payload).unwrap_or_else(|_| format!("\"{}\"", payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Check if this is a pattern message
-    if let Ok(pattern) = msg.get_pattern::<String>() {
-        Some(PubSubMessage::PMessage {
-            pattern,
-            channel,
+    fn message(payload: &[u8], base64: bool) -> PubSubMessage {
+        let (payload, base64) = encode_payload(payload, base64);
+        PubSubMessage::Message {
+            channel: "news".into(),
             payload,
-        })
-    } else {
-        Some(PubSubMessage::Message { channel, payload })
+            base64,
+        }
+    }
+
+    #[test]
+    fn json_payloads_are_always_strings() {
+        let payloads: Vec<_> = [&b"\"hello\""[..], b"hello", b"123"]
+            .into_iter()
+            .map(|p| message(p, false).to_json()["payload"].clone())
+            .collect();
+        assert_eq!(payloads, [json!("\"hello\""), json!("hello"), json!("123")]);
     }
-}
 
+    #[test]
+    fn json_flags_base64_payloads() {
+        assert_eq!(
+            message(&[0xff, 0x00], false).to_json(),
+            json!({"type": "message", "channel": "news", "payload": "/wA=", "encoding": "base64"})
+        );
+        assert_eq!(message(b"hi", true).to_json()["payload"], json!("aGk="));
+    }
+
+    #[test]
+    fn upstash_format_embeds_json_payloads() {
+        assert_eq!(
+            format_sse_message(&message(b"{\"id\":1}", false)),
+            "message,news,{\"id\":1}"
+        );
+        assert_eq!(
+            format_sse_message(&message(b"123", false)),
+            "message,news,123"
+        );
+        assert_eq!(
+            format_sse_message(&message(b"hello", false)),
+            "message,news,\"hello\""
+        );
+        assert_eq!(
+            format_sse_message(&message(b"123", true)),
+            "message,news,\"MTIz\""
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use crate::metrics::{command_label, METRICS};
+use crate::telemetry::db_index;
 use redis::{aio::ConnectionManager, Cmd, Pipeline, Value};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::time::timeout;
+use tracing::Instrument;
 
 /// Convert Redis Value to JSON, preserving type semantics for Upstash compatibility
 fn redis_to_json(v: Value) -> serde_json::Value {
@@ -81,7 +84,7 @@ fn normalize_command(cmd: &mut [String]) {
     let name = cmd[0].to_ascii_lowercase();
     
     // Handle FUNCTION LOAD - strip leading whitespace from script code
-    if name == "function" && cmd.len() >= 3 && cmd[1].to_ascii_lowercase() == "load" {
+    if name == "function" && cmd.len() >= 3 && cmd[1].eq_ignore_ascii_case("load") {
         // Find the code argument (last argument)
         let code_idx = cmd.len() - 1;
         let code = &cmd[code_idx];
@@ -92,40 +95,82 @@ fn normalize_command(cmd: &mut [String]) {
             cmd[code_idx] = trimmed.to_string();
         }
     }
-    
-    // Handle read-only command variants
+
+    // Read-only variants fall back to their base command, which Redis <7
+    // also knows. Policy keeps read-only tokens from using them.
+    if let Some(base) = ro_fallback(&name) {
+        cmd[0] = base.to_string();
+    }
+}
+
+/// The command a read-only script variant (`EVAL_RO`, `EVALSHA_RO`,
+/// `FCALL_RO`) is sent as
+pub(crate) fn ro_fallback(name: &str) -> Option<&'static str> {
     let base = name
         .strip_suffix("_ro")
-        .or_else(|| name.strip_suffix("ro"))
-        .unwrap_or(name.as_str());
-
+        .or_else(|| name.strip_suffix("ro"))?;
     // Commands that have read-only variants that should fall back to base command
     const RO_FALLBACKS: &[&str] = &["eval", "evalsha", "fcall"];
-    if RO_FALLBACKS.contains(&base) {
-        cmd[0] = base.to_string();
+    RO_FALLBACKS.iter().find(|&&c| c == base).copied()
+}
+
+/// The client span around one round trip to Redis, with the OpenTelemetry
+/// database attributes. `db.namespace` is a string and the batch size an
+/// integer, as the conventions ask.
+fn redis_span(operation: &str, batch_size: Option<usize>) -> tracing::Span {
+    match batch_size {
+        None => tracing::info_span!(
+            "redis.command",
+            otel.name = %operation,
+            otel.kind = "client",
+            db.system = "redis",
+            db.operation.name = %operation,
+            db.namespace = %db_index(),
+        ),
+        Some(size) => tracing::info_span!(
+            "redis.pipeline",
+            otel.name = %operation,
+            otel.kind = "client",
+            db.system = "redis",
+            db.operation.name = %operation,
+            db.operation.batch.size = size as i64,
+            db.namespace = %db_index(),
+        ),
     }
 }
 
 pub async fn do_call(
     conn: &mut ConnectionManager,
     cmd: Vec<String>,
+    deadline: Duration,
 ) -> anyhow::Result<serde_json::Value> {
     if cmd.is_empty() {
         anyhow::bail!("empty command")
     }
     let mut cmd = cmd;
     normalize_command(&mut cmd);
+    let label = command_label(cmd.first().map(String::as_str));
     let mut redis_cmd = Cmd::new();
     for a in cmd {
         redis_cmd.arg(a);
     }
-    let v: Value = timeout(Duration::from_secs(3), redis_cmd.query_async(conn)).await??;
-    Ok(redis_to_json(v))
+    let span = redis_span(&label, None);
+    let start = Instant::now();
+    let res: anyhow::Result<Value> = match timeout(deadline, redis_cmd.query_async(conn))
+        .instrument(span)
+        .await
+    {
+        Ok(r) => r.map_err(Into::into),
+        Err(e) => Err(e.into()),
+    };
+    METRICS.observe_redis(&label, start.elapsed(), &res);
+    Ok(redis_to_json(res?))
 }
 
 pub async fn execute_pipeline(
     conn: &mut ConnectionManager,
     cmds: Vec<Vec<String>>,
+    deadline: Duration,
 ) -> anyhow::Result<Vec<serde_json::Value>> {
     if cmds.is_empty() {
         return Ok(vec![]);
@@ -143,6 +188,102 @@ pub async fn execute_pipeline(
         pipe.add_command(c);
     }
 
-    let results: Vec<Value> = timeout(Duration::from_secs(10), pipe.query_async(conn)).await??;
-    Ok(results.into_iter().map(redis_to_json).collect())
+    let span = redis_span("pipeline", Some(pipe.len()));
+    let start = Instant::now();
+    let res: anyhow::Result<Vec<Value>> = match timeout(deadline, pipe.query_async(conn))
+        .instrument(span)
+        .await
+    {
+        Ok(r) => r.map_err(Into::into),
+        Err(e) => Err(e.into()),
+    };
+    METRICS.observe_redis("pipeline", start.elapsed(), &res);
+    Ok(res?.into_iter().map(redis_to_json).collect())
+}
+
+/// Publish one payload to several channels in a single round trip, returning
+/// how many clients received it on each
+pub async fn publish(
+    conn: &mut ConnectionManager,
+    cmds: Vec<Cmd>,
+    deadline: Duration,
+) -> anyhow::Result<Vec<i64>> {
+    let mut pipe = Pipeline::new();
+    for c in cmds {
+        pipe.add_command(c);
+    }
+    let span = redis_span("publish", Some(pipe.len()));
+    let start = Instant::now();
+    let res: anyhow::Result<Vec<i64>> = match timeout(deadline, pipe.query_async(conn))
+        .instrument(span)
+        .await
+    {
+        Ok(r) => r.map_err(Into::into),
+        Err(e) => Err(e.into()),
+    };
+    METRICS.observe_redis("publish", start.elapsed(), &res);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::set_db_index;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry::{KeyValue, Value};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// The attributes of the span `make` creates, as exported over OTLP
+    fn exported(make: impl FnOnce() -> tracing::Span) -> Vec<KeyValue> {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || drop(make()));
+        let mut spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        spans.remove(0).attributes
+    }
+
+    fn attribute(attributes: &[KeyValue], key: &str) -> Option<Value> {
+        attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn command_span_has_database_attributes() {
+        set_db_index(3);
+        let attributes = exported(|| redis_span("get", None));
+        assert_eq!(
+            attribute(&attributes, "db.namespace"),
+            Some(Value::from("3"))
+        );
+        assert_eq!(
+            attribute(&attributes, "db.system"),
+            Some(Value::from("redis"))
+        );
+        assert_eq!(
+            attribute(&attributes, "db.operation.name"),
+            Some(Value::from("get"))
+        );
+        assert_eq!(attribute(&attributes, "db.operation.batch.size"), None);
+    }
+
+    #[test]
+    fn pipeline_span_has_batch_size() {
+        let attributes = exported(|| redis_span("pipeline", Some(4)));
+        assert_eq!(
+            attribute(&attributes, "db.operation.batch.size"),
+            Some(Value::I64(4))
+        );
+        assert!(matches!(
+            attribute(&attributes, "db.namespace"),
+            Some(Value::String(_))
+        ));
+    }
 }
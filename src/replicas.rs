@@ -0,0 +1,176 @@
+use crate::upstream::{is_unavailable, Upstream};
+use redis::aio::ConnectionManager;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// How a read-only command picks its replica
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaStrategy {
+    RoundRobin,
+    LeastLatency,
+}
+
+impl FromStr for ReplicaStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "round_robin" | "rr" => Ok(ReplicaStrategy::RoundRobin),
+            "least_latency" | "latency" => Ok(ReplicaStrategy::LeastLatency),
+            other => anyhow::bail!("unknown replica strategy: {}", other),
+        }
+    }
+}
+
+pub struct Replica {
+    pub upstream: Upstream,
+    healthy: AtomicBool,
+    /// Smoothed PING round trip in microseconds
+    latency_micros: AtomicU64,
+}
+
+impl Replica {
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.latency_micros.load(Ordering::Relaxed))
+    }
+}
+
+/// Replica endpoints that serve read-only traffic
+pub struct ReplicaSet {
+    replicas: Vec<Replica>,
+    strategy: ReplicaStrategy,
+    next: AtomicUsize,
+}
+
+impl ReplicaSet {
+    /// Start connecting to every replica URL in the background. Replicas stay
+    /// out of rotation until their first successful health check.
+    pub fn new(
+        urls: &[String],
+        strategy: ReplicaStrategy,
+        connect_timeout: Duration,
+        pool_size: usize,
+    ) -> Self {
+        let replicas = urls
+            .iter()
+            .map(|url| {
+                let upstream = Upstream::new(url.clone(), connect_timeout, pool_size);
+                upstream.spawn();
+                Replica {
+                    upstream,
+                    healthy: AtomicBool::new(false),
+                    latency_micros: AtomicU64::new(0),
+                }
+            })
+            .collect();
+        ReplicaSet {
+            replicas,
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn replicas(&self) -> &[Replica] {
+        &self.replicas
+    }
+
+    /// Pick a healthy replica, returning its index and a connection handle.
+    /// `None` means the caller should fall back to the primary.
+    pub fn pick(&self) -> Option<(usize, ConnectionManager)> {
+        let healthy = self
+            .replicas
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_healthy());
+        let idx = match self.strategy {
+            ReplicaStrategy::RoundRobin => {
+                let candidates: Vec<usize> = healthy.map(|(i, _)| i).collect();
+                if candidates.is_empty() {
+                    return None;
+                }
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates[n % candidates.len()]
+            }
+            ReplicaStrategy::LeastLatency => {
+                healthy
+                    .min_by_key(|(_, r)| r.latency_micros.load(Ordering::Relaxed))?
+                    .0
+            }
+        };
+        let conn = self.replicas[idx].upstream.connection().ok()?;
+        Some((idx, conn))
+    }
+
+    /// Take a replica out of rotation until the next successful health check
+    pub fn mark_unhealthy(&self, idx: usize) {
+        if let Some(r) = self.replicas.get(idx) {
+            r.healthy.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Close every replica connection
+    pub fn close(&self) {
+        for r in &self.replicas {
+            r.healthy.store(false, Ordering::Relaxed);
+            r.upstream.close();
+        }
+    }
+
+    /// PING every replica on an interval, updating health and latency
+    pub fn spawn_health_checks(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                for r in &self.replicas {
+                    let Ok(mut conn) = r.upstream.connection() else {
+                        r.healthy.store(false, Ordering::Relaxed);
+                        continue;
+                    };
+                    let start = Instant::now();
+                    let ping = redis::cmd("PING");
+                    match timeout(
+                        Duration::from_secs(1),
+                        ping.query_async::<String>(&mut conn),
+                    )
+                    .await
+                    {
+                        Ok(Ok(_)) => {
+                            let sample = start.elapsed().as_micros() as u64;
+                            let prev = r.latency_micros.load(Ordering::Relaxed);
+                            let smoothed = if prev == 0 {
+                                sample
+                            } else {
+                                (prev * 4 + sample) / 5
+                            };
+                            r.latency_micros.store(smoothed, Ordering::Relaxed);
+                            r.healthy.store(true, Ordering::Relaxed);
+                        }
+                        _ => r.healthy.store(false, Ordering::Relaxed),
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Whether an error from a replica means the replica itself is unusable
+/// (as opposed to a Redis error reply that the primary would repeat).
+pub fn is_connection_failure(e: &anyhow::Error) -> bool {
+    if e.is::<tokio::time::error::Elapsed>() {
+        return true;
+    }
+    if is_unavailable(e) {
+        return true;
+    }
+    matches!(e.downcast_ref::<redis::RedisError>(), Some(re) if re.is_timeout())
+}
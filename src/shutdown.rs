@@ -0,0 +1,149 @@
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long SSE clients should wait before reconnecting after shutdown
+pub const RECONNECT_AFTER: Duration = Duration::from_secs(1);
+
+/// Process-wide shutdown flag. Cloning is cheap; all clones observe the same
+/// signal.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+    /// Open [`Hold`]s
+    holds: watch::Sender<usize>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            tx: watch::Sender::new(false),
+            holds: watch::Sender::new(0),
+        }
+    }
+}
+
+/// Keeps the process from exiting until dropped, or until the drain
+/// deadline. See [`Shutdown::hold`].
+pub struct Hold {
+    holds: watch::Sender<usize>,
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        self.holds.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolve once shutdown has started
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        let _ = rx.wait_for(|v| *v).await;
+    }
+
+    /// Ask for shutdown to wait for the caller. For work the HTTP server
+    /// doesn't drain itself, such as `/ws` sessions, which it stops
+    /// tracking once upgraded.
+    pub fn hold(&self) -> Hold {
+        self.holds.send_modify(|n| *n += 1);
+        Hold {
+            holds: self.holds.clone(),
+        }
+    }
+
+    /// Resolve once every [`Hold`] is dropped
+    pub async fn idle(&self) {
+        let mut rx = self.holds.subscribe();
+        let _ = rx.wait_for(|n| *n == 0).await;
+    }
+}
+
+/// Resolve on SIGTERM or Ctrl-C
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let term = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let term = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = term => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wakes_every_waiting_clone() {
+        let shutdown = Shutdown::default();
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let s = shutdown.clone();
+                tokio::spawn(async move { s.wait().await })
+            })
+            .collect();
+        tokio::task::yield_now().await;
+        assert!(!shutdown.is_triggered());
+        assert!(waiters.iter().all(|w| !w.is_finished()));
+
+        shutdown.trigger();
+        for w in waiters {
+            tokio::time::timeout(Duration::from_secs(1), w)
+                .await
+                .expect("waiter woke up")
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn late_waiters_return_at_once() {
+        let shutdown = Shutdown::default();
+        shutdown.trigger();
+        let late = shutdown.clone();
+        assert!(late.is_triggered());
+        tokio::time::timeout(Duration::from_millis(100), late.wait())
+            .await
+            .expect("already triggered");
+    }
+
+    #[tokio::test]
+    async fn idle_waits_for_holds() {
+        let shutdown = Shutdown::default();
+        tokio::time::timeout(Duration::from_millis(100), shutdown.idle())
+            .await
+            .expect("nothing held");
+
+        let hold = shutdown.hold();
+        let idle = tokio::spawn({
+            let s = shutdown.clone();
+            async move { s.idle().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!idle.is_finished());
+
+        drop(hold);
+        tokio::time::timeout(Duration::from_secs(1), idle)
+            .await
+            .expect("idle once released")
+            .unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+use crate::handlers::{authorize, error_status, malformed, observe_stream};
+use crate::logging::RequestLog;
+use crate::metrics::{command_label, SseGuard, METRICS};
+use crate::models::{AppState, EnvResp};
+use crate::policy::Principal;
+use crate::pubsub::{reconnect_event, SseFormat};
+use crate::redis_client::do_call;
+use crate::utils::{encode_result_value, write_resp};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use futures::stream::Stream;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// Query parameters of `/xreadgroup`
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ReadGroupParams {
+    /// Most entries fetched per read
+    pub count: usize,
+    /// How long each read waits for new entries before trying again
+    pub block_ms: u64,
+    /// Create the group at `$`, and the stream if needed, when it is missing
+    pub create: bool,
+    /// Deliver the consumer's pending entries before new ones
+    pub pending: bool,
+}
+
+impl Default for ReadGroupParams {
+    fn default() -> Self {
+        ReadGroupParams {
+            count: 100,
+            block_ms: 5000,
+            create: false,
+            pending: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClaimBody {
+    pub min_idle_ms: u64,
+    pub ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoClaimBody {
+    pub min_idle_ms: u64,
+    #[serde(default = "AutoClaimBody::default_start")]
+    pub start: String,
+    #[serde(default = "AutoClaimBody::default_count")]
+    pub count: usize,
+}
+
+impl AutoClaimBody {
+    fn default_start() -> String {
+        "0-0".into()
+    }
+
+    fn default_count() -> usize {
+        100
+    }
+}
+
+/// Query parameters of `/xpending`
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PendingParams {
+    pub start: String,
+    pub end: String,
+    pub count: usize,
+    /// Only entries delivered to this consumer
+    pub consumer: Option<String>,
+    /// Only entries idle for at least this long
+    pub idle_ms: Option<u64>,
+}
+
+impl Default for PendingParams {
+    fn default() -> Self {
+        PendingParams {
+            start: "-".into(),
+            end: "+".into(),
+            count: 100,
+            consumer: None,
+            idle_ms: None,
+        }
+    }
+}
+
+/// A stream entry as `{"id", "fields"}`. Entries deleted since they were
+/// delivered have `null` fields.
+fn entry_json(entry: Value) -> Option<Value> {
+    let Value::Array(mut pair) = entry else {
+        return None;
+    };
+    if pair.len() != 2 {
+        return None;
+    }
+    let fields = match pair.pop() {
+        Some(Value::Array(flat)) => {
+            let mut map = serde_json::Map::new();
+            for kv in flat.chunks(2) {
+                if let [Value::String(k), v] = kv {
+                    map.insert(k.clone(), v.clone());
+                }
+            }
+            Value::Object(map)
+        }
+        _ => Value::Null,
+    };
+    Some(json!({"id": pair.pop(), "fields": fields}))
+}
+
+fn entry_list(entries: Value) -> Vec<Value> {
+    match entries {
+        Value::Array(entries) => entries.into_iter().filter_map(entry_json).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Flatten an `XREADGROUP` reply into entries tagged with their stream. A
+/// read that timed out is `null` and yields nothing.
+fn read_entries(reply: Value) -> Vec<Value> {
+    let per_stream: Vec<(Value, Value)> = match reply {
+        // RESP2: [[stream, entries], ...]
+        Value::Array(streams) => streams
+            .into_iter()
+            .filter_map(|s| match s {
+                Value::Array(mut pair) if pair.len() == 2 => {
+                    let entries = pair.pop()?;
+                    Some((pair.pop()?, entries))
+                }
+                _ => None,
+            })
+            .collect(),
+        // RESP3: {stream: entries}
+        Value::Object(map) => map.into_iter().map(|(k, v)| (json!(k), v)).collect(),
+        _ => Vec::new(),
+    };
+    let mut out = Vec::new();
+    for (stream, entries) in per_stream {
+        for mut entry in entry_list(entries) {
+            entry["stream"] = stream.clone();
+            out.push(entry);
+        }
+    }
+    out
+}
+
+/// Reads one consumer's share of a group. Blocking reads would hold up every
+/// request multiplexed on a shared connection, so each reader gets its own.
+struct Reader {
+    group: String,
+    consumer: String,
+    count: usize,
+    streams: Vec<String>,
+    /// The last pending entry delivered per stream, while the consumer's
+    /// pending entries are being replayed
+    pending: Option<Vec<String>>,
+}
+
+impl Reader {
+    fn command(&self, block: Option<u64>) -> Vec<String> {
+        let mut cmd = vec![
+            "XREADGROUP".to_string(),
+            "GROUP".into(),
+            self.group.clone(),
+            self.consumer.clone(),
+            "COUNT".into(),
+            self.count.to_string(),
+        ];
+        if let (Some(ms), None) = (block, &self.pending) {
+            cmd.extend(["BLOCK".into(), ms.to_string()]);
+        }
+        cmd.push("STREAMS".into());
+        cmd.extend(self.streams.iter().cloned());
+        match &self.pending {
+            Some(ids) => cmd.extend(ids.iter().cloned()),
+            None => cmd.extend(self.streams.iter().map(|_| ">".to_string())),
+        }
+        cmd
+    }
+
+    async fn read(
+        &mut self,
+        conn: &mut ConnectionManager,
+        block: Option<u64>,
+        deadline: Duration,
+    ) -> anyhow::Result<Vec<Value>> {
+        let deadline = match (block, &self.pending) {
+            (Some(ms), None) => deadline + Duration::from_millis(ms),
+            _ => deadline,
+        };
+        let entries = read_entries(do_call(conn, self.command(block), deadline).await?);
+        if let Some(ids) = &mut self.pending {
+            for entry in &entries {
+                let idx = self.streams.iter().position(|s| entry["stream"] == *s);
+                if let (Some(idx), Some(id)) = (idx, entry["id"].as_str()) {
+                    ids[idx] = id.to_string();
+                }
+            }
+        }
+        // Pending entries are done once a read comes back empty
+        if entries.is_empty() {
+            self.pending = None;
+        }
+        Ok(entries)
+    }
+}
+
+/// A connection of its own for a blocking reader
+async fn blocking_connection(state: &AppState) -> anyhow::Result<ConnectionManager> {
+    let connect = state.config.timeouts.connect();
+    let config = ConnectionManagerConfig::new()
+        .set_connection_timeout(Some(connect))
+        .set_response_timeout(None);
+    let client = redis::Client::open(state.redis_url.as_str())?;
+    Ok(timeout(connect, client.get_connection_manager_with_config(config)).await??)
+}
+
+fn group_create(stream: &str, group: &str) -> Vec<String> {
+    vec![
+        "XGROUP".into(),
+        "CREATE".into(),
+        stream.into(),
+        group.into(),
+        "$".into(),
+        "MKSTREAM".into(),
+    ]
+}
+
+fn failure(log: &RequestLog, e: &anyhow::Error, what: &str) -> Response {
+    log.set_error(e.to_string());
+    write_resp(
+        EnvResp {
+            status: error_status(e),
+            result: None,
+            result_list: None,
+            error: Some(format!("{}: {}", what, e)),
+            message: None,
+        },
+        false,
+    )
+}
+
+fn entry_event(entry: &Value) -> Event {
+    let event = Event::default().data(entry.to_string());
+    match entry["id"].as_str() {
+        Some(id) => event.id(id),
+        None => event,
+    }
+}
+
+pub async fn get_xreadgroup(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    Path((group, consumer, streams)): Path<(String, String, String)>,
+    params: Result<Query<ReadGroupParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let start = Instant::now();
+    let res = read_group_stream(state, &principal, &log, group, consumer, streams, params).await;
+    observe_stream("/xreadgroup", "xreadgroup", &res, start);
+    res
+}
+
+async fn read_group_stream(
+    state: AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    group: String,
+    consumer: String,
+    streams: String,
+    params: Result<Query<ReadGroupParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let Query(params) = params.map_err(|e| malformed(e.body_text()))?;
+    if params.count == 0 || params.block_ms == 0 {
+        return Err(malformed("count and block_ms must be positive.".into()));
+    }
+    let streams: Vec<String> = streams
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect();
+    if streams.is_empty() {
+        return Err(malformed("No streams specified".into()));
+    }
+
+    let mut reader = Reader {
+        group,
+        consumer,
+        count: params.count,
+        pending: params.pending.then(|| vec!["0".to_string(); streams.len()]),
+        streams,
+    };
+    let mut cmds = Vec::new();
+    if params.create {
+        for stream in &reader.streams {
+            cmds.push(group_create(stream, &reader.group));
+        }
+    }
+    cmds.push(reader.command(Some(params.block_ms)));
+    log.set_commands(&cmds);
+    if let Some(resp) = authorize(&state, principal, log, &cmds, false) {
+        return Err(resp);
+    }
+
+    let mut conn = blocking_connection(&state)
+        .await
+        .map_err(|e| failure(log, &e, "Failed to connect"))?;
+    let deadline = state.config.timeouts.command();
+    if params.create {
+        for stream in &reader.streams {
+            match do_call(&mut conn, group_create(stream, &reader.group), deadline).await {
+                Err(e) if !e.to_string().contains("BUSYGROUP") => {
+                    return Err(failure(log, &e, "Failed to create group"))
+                }
+                _ => {}
+            }
+        }
+    }
+    // Read once without blocking so that a missing group or a wrong key type
+    // is an HTTP error rather than an event
+    let first = reader
+        .read(&mut conn, None, deadline)
+        .await
+        .map_err(|e| failure(log, &e, "Failed to read"))?;
+
+    let shutdown = state.shutdown.clone();
+    let block = params.block_ms;
+    let stream = async_stream::stream! {
+        let _guard = SseGuard::new(reader.streams.len());
+        for entry in &first {
+            yield Ok(entry_event(entry));
+        }
+        loop {
+            let next = tokio::select! {
+                res = reader.read(&mut conn, Some(block), deadline) => Some(res),
+                _ = shutdown.wait() => None,
+            };
+            let Some(res) = next else {
+                yield Ok(reconnect_event(SseFormat::Json));
+                break;
+            };
+            match res {
+                Ok(entries) => {
+                    for entry in &entries {
+                        yield Ok(entry_event(entry));
+                    }
+                }
+                Err(e) => {
+                    // Entries read but not acknowledged stay pending, so a
+                    // client that reconnects with `?pending=true` loses nothing
+                    tracing::warn!(error = %e, "consumer group read failed");
+                    let data = json!({"type": "read_error", "error": e.to_string()});
+                    yield Ok(Event::default().event("read_error").data(data.to_string()));
+                    break;
+                }
+            }
+        }
+    };
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Run one companion command on the primary and shape its reply
+async fn run_group_command(
+    state: &AppState,
+    principal: &Principal,
+    log: &RequestLog,
+    headers: &HeaderMap,
+    route: &str,
+    cmd: Vec<String>,
+    shape: fn(Value) -> Value,
+) -> Response {
+    let start = Instant::now();
+    let label = command_label(Some(&cmd[0]));
+    let enc = headers
+        .get("upstash-encoding")
+        .and_then(|v| v.to_str().ok())
+        == Some("base64");
+    log.set_commands(std::slice::from_ref(&cmd));
+    let resp = match authorize(state, principal, log, std::slice::from_ref(&cmd), enc) {
+        Some(resp) => resp,
+        None => {
+            let deadline = state.config.timeouts.command();
+            let res = match state.conn.connection() {
+                Ok(mut conn) => do_call(&mut conn, cmd, deadline).await,
+                Err(e) => Err(e),
+            };
+            match res {
+                // Not through `write_resp`, which sends arrays of objects
+                // bare as if they were pipeline results
+                Ok(v) => {
+                    let v = shape(v);
+                    let v = if enc { encode_result_value(v) } else { v };
+                    Json(json!({ "result": v })).into_response()
+                }
+                Err(e) => {
+                    log.set_error(e.to_string());
+                    write_resp(
+                        EnvResp {
+                            status: error_status(&e),
+                            result: None,
+                            result_list: None,
+                            error: Some(e.to_string()),
+                            message: None,
+                        },
+                        enc,
+                    )
+                }
+            }
+        }
+    };
+    METRICS.observe_request(route, &label, resp.status(), start.elapsed());
+    resp
+}
+
+/// `POST /xack/{stream}/{group}` with a JSON array of entry IDs
+pub async fn post_xack(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    Path((stream, group)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Result<Json<Vec<String>>, JsonRejection>,
+) -> Response {
+    let ids = match body {
+        Ok(Json(ids)) if !ids.is_empty() => ids,
+        Ok(_) => return malformed("Expected at least one entry ID.".into()),
+        Err(e) => return malformed(e.body_text()),
+    };
+    let mut cmd = vec!["XACK".to_string(), stream, group];
+    cmd.extend(ids);
+    run_group_command(&state, &principal, &log, &headers, "/xack", cmd, |v| v).await
+}
+
+/// `POST /xclaim/{stream}/{group}/{consumer}`
+pub async fn post_xclaim(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    Path((stream, group, consumer)): Path<(String, String, String)>,
+    headers: HeaderMap,
+    body: Result<Json<ClaimBody>, JsonRejection>,
+) -> Response {
+    let body = match body {
+        Ok(Json(body)) if !body.ids.is_empty() => body,
+        Ok(_) => return malformed("Expected at least one entry ID.".into()),
+        Err(e) => return malformed(e.body_text()),
+    };
+    let mut cmd = vec![
+        "XCLAIM".to_string(),
+        stream,
+        group,
+        consumer,
+        body.min_idle_ms.to_string(),
+    ];
+    cmd.extend(body.ids);
+    run_group_command(&state, &principal, &log, &headers, "/xclaim", cmd, |v| {
+        Value::Array(entry_list(v))
+    })
+    .await
+}
+
+/// `POST /xautoclaim/{stream}/{group}/{consumer}`
+pub async fn post_xautoclaim(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    Path((stream, group, consumer)): Path<(String, String, String)>,
+    headers: HeaderMap,
+    body: Result<Json<AutoClaimBody>, JsonRejection>,
+) -> Response {
+    let body = match body {
+        Ok(Json(body)) => body,
+        Err(e) => return malformed(e.body_text()),
+    };
+    let cmd = vec![
+        "XAUTOCLAIM".to_string(),
+        stream,
+        group,
+        consumer,
+        body.min_idle_ms.to_string(),
+        body.start,
+        "COUNT".into(),
+        body.count.to_string(),
+    ];
+    run_group_command(
+        &state,
+        &principal,
+        &log,
+        &headers,
+        "/xautoclaim",
+        cmd,
+        |v| {
+            // [next, entries, deleted IDs]; Redis 6.2 leaves out the last one
+            let mut parts = match v {
+                Value::Array(parts) => parts.into_iter(),
+                _ => Vec::new().into_iter(),
+            };
+            let next = parts.next().unwrap_or(Value::Null);
+            let entries = entry_list(parts.next().unwrap_or(Value::Null));
+            let deleted = parts.next().unwrap_or_else(|| json!([]));
+            json!({"next": next, "entries": entries, "deleted": deleted})
+        },
+    )
+    .await
+}
+
+/// `GET /xpending/{stream}/{group}`, one object per pending entry
+pub async fn get_xpending(
+    State(state): State<AppState>,
+    Extension(log): Extension<RequestLog>,
+    Extension(principal): Extension<Arc<Principal>>,
+    Path((stream, group)): Path<(String, String)>,
+    headers: HeaderMap,
+    params: Result<Query<PendingParams>, QueryRejection>,
+) -> Response {
+    let params = match params {
+        Ok(Query(params)) => params,
+        Err(e) => return malformed(e.body_text()),
+    };
+    let mut cmd = vec!["XPENDING".to_string(), stream, group];
+    if let Some(ms) = params.idle_ms {
+        cmd.extend(["IDLE".into(), ms.to_string()]);
+    }
+    cmd.extend([params.start, params.end, params.count.to_string()]);
+    cmd.extend(params.consumer);
+    run_group_command(&state, &principal, &log, &headers, "/xpending", cmd, |v| {
+        let Value::Array(rows) = v else {
+            return json!([]);
+        };
+        let rows = rows.into_iter().filter_map(|row| match row {
+            Value::Array(row) if row.len() == 4 => Some(json!({
+                "id": row[0],
+                "consumer": row[1],
+                "idle_ms": row[2],
+                "deliveries": row[3],
+            })),
+            _ => None,
+        });
+        Value::Array(rows.collect())
+    })
+    .await
+}
@@ -0,0 +1,123 @@
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::Context;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Logical database of the primary, attached to Redis spans as `db.namespace`
+static DB_INDEX: AtomicI64 = AtomicI64::new(0);
+
+pub fn set_db_index(db: i64) {
+    DB_INDEX.store(db, Ordering::Relaxed);
+}
+
+pub fn db_index() -> i64 {
+    DB_INDEX.load(Ordering::Relaxed)
+}
+
+/// Build an OTLP/HTTP span exporter for `endpoint` (the collector base URL,
+/// e.g. `http://localhost:4318`) and return the provider plus a tracer
+pub fn init_tracer(
+    endpoint: &str,
+    service_name: &str,
+) -> anyhow::Result<(SdkTracerProvider, SdkTracer)> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_endpoint(endpoint))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer("serverless-redis");
+    Ok((provider, tracer))
+}
+
+/// The OTLP/HTTP traces URL for a collector base URL, or the URL itself if
+/// it already names the traces path
+fn traces_endpoint(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Parent context from the W3C `traceparent`/`tracestate` request headers
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_endpoint_appends_path() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("https://collector/otlp"),
+            "https://collector/otlp/v1/traces"
+        );
+    }
+
+    #[test]
+    fn traces_endpoint_keeps_full_url() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/v1/traces"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/v1/traces/"),
+            "http://localhost:4318/v1/traces"
+        );
+    }
+
+    #[test]
+    fn extracts_w3c_parent() {
+        use opentelemetry::trace::TraceContextExt;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let cx = extract_context(&headers);
+        let span = cx.span();
+        let parent = span.span_context();
+        assert!(parent.is_remote());
+        assert_eq!(
+            parent.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+    }
+}
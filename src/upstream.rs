@@ -0,0 +1,283 @@
+use crate::utils::redact_url;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::fmt;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Connection state of an upstream Redis endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamStatus {
+    /// No connection has been established yet
+    Connecting,
+    Connected,
+    /// A connection exists but the last health check failed
+    Disconnected,
+    /// Shut down by `close`
+    Closed,
+}
+
+impl UpstreamStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpstreamStatus::Connecting => "connecting",
+            UpstreamStatus::Connected => "connected",
+            UpstreamStatus::Disconnected => "disconnected",
+            UpstreamStatus::Closed => "closed",
+        }
+    }
+}
+
+/// Returned when a command arrives before the upstream is reachable
+#[derive(Debug)]
+pub struct UpstreamUnavailable {
+    pub url: String,
+}
+
+impl fmt::Display for UpstreamUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Redis is not reachable at {}", redact_url(&self.url))
+    }
+}
+
+impl std::error::Error for UpstreamUnavailable {}
+
+/// A Redis endpoint that is connected lazily and kept alive in the background.
+///
+/// Holds a pool of multiplexed connections; commands are spread over them
+/// round-robin. Cloning is cheap; all clones share the same pool.
+#[derive(Clone)]
+pub struct Upstream {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    url: String,
+    connect_timeout: Duration,
+    pool_size: usize,
+    /// Empty until the first successful connect
+    pool: watch::Sender<Vec<ConnectionManager>>,
+    next: AtomicUsize,
+    status: AtomicU8,
+    last_error: Mutex<Option<String>>,
+    task: Mutex<Option<tokio::task::AbortHandle>>,
+}
+
+impl Upstream {
+    pub fn new(url: impl Into<String>, connect_timeout: Duration, pool_size: usize) -> Self {
+        Upstream {
+            inner: Arc::new(Inner {
+                url: url.into(),
+                connect_timeout,
+                pool_size: pool_size.max(1),
+                pool: watch::Sender::new(Vec::new()),
+                next: AtomicUsize::new(0),
+                status: AtomicU8::new(UpstreamStatus::Connecting as u8),
+                last_error: Mutex::new(None),
+                task: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.inner.url
+    }
+
+    /// A handle to the next connection in the pool, or `UpstreamUnavailable`
+    /// if Redis has not been reached yet
+    pub fn connection(&self) -> anyhow::Result<ConnectionManager> {
+        let pool = self.inner.pool.borrow();
+        if pool.is_empty() {
+            return Err(UpstreamUnavailable {
+                url: self.inner.url.clone(),
+            }
+            .into());
+        }
+        let n = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        Ok(pool[n % pool.len()].clone())
+    }
+
+    pub fn status(&self) -> UpstreamStatus {
+        match self.inner.status.load(Ordering::Relaxed) {
+            x if x == UpstreamStatus::Connected as u8 => UpstreamStatus::Connected,
+            x if x == UpstreamStatus::Disconnected as u8 => UpstreamStatus::Disconnected,
+            x if x == UpstreamStatus::Closed as u8 => UpstreamStatus::Closed,
+            _ => UpstreamStatus::Connecting,
+        }
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.last_error.lock().unwrap().clone()
+    }
+
+    /// Wait until the first connection has been established
+    pub async fn wait_connected(&self) -> ConnectionManager {
+        let mut rx = self.inner.pool.subscribe();
+        let pool = rx
+            .wait_for(|p| !p.is_empty())
+            .await
+            .expect("sender is alive");
+        pool[0].clone()
+    }
+
+    fn set_status(&self, status: UpstreamStatus, error: Option<String>) {
+        self.inner.status.store(status as u8, Ordering::Relaxed);
+        *self.inner.last_error.lock().unwrap() = error;
+    }
+
+    /// Connect in the background with exponential backoff, then keep
+    /// checking every pooled connection so `status` stays current.
+    ///
+    /// Once established, each `ConnectionManager` reconnects on its own after
+    /// a drop; this task only observes them.
+    pub fn spawn(&self) {
+        let this = self.clone();
+        let task = tokio::spawn(async move {
+            let mut pool = this.connect_with_backoff().await;
+            let mut ticker = tokio::time::interval(PING_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let pings = pool.iter_mut().map(|conn| async move {
+                    let ping = redis::cmd("PING");
+                    timeout(Duration::from_secs(1), ping.query_async::<String>(conn)).await
+                });
+                let failure = futures::future::join_all(pings)
+                    .await
+                    .into_iter()
+                    .find_map(|r| match r {
+                        Ok(Ok(_)) => None,
+                        Ok(Err(e)) => Some(e.to_string()),
+                        Err(_) => Some("PING timed out".to_string()),
+                    });
+                match failure {
+                    None => {
+                        if this.status() != UpstreamStatus::Connected {
+                            tracing::info!(url = %redact_url(&this.inner.url), "reconnected to Redis");
+                        }
+                        this.set_status(UpstreamStatus::Connected, None)
+                    }
+                    Some(error) => this.mark_disconnected(error),
+                }
+            }
+        });
+        *self.inner.task.lock().unwrap() = Some(task.abort_handle());
+    }
+
+    /// Stop reconnecting and drop the shared connection. Handles already
+    /// given out keep working until they are dropped too.
+    pub fn close(&self) {
+        if let Some(task) = self.inner.task.lock().unwrap().take() {
+            task.abort();
+        }
+        self.inner.pool.send_replace(Vec::new());
+        self.set_status(UpstreamStatus::Closed, None);
+    }
+
+    fn mark_disconnected(&self, error: String) {
+        if self.status() == UpstreamStatus::Connected {
+            tracing::error!(url = %redact_url(&self.inner.url), error, "lost connection to Redis");
+        }
+        self.set_status(UpstreamStatus::Disconnected, Some(error));
+    }
+
+    async fn connect_with_backoff(&self) -> Vec<ConnectionManager> {
+        let mut delay = INITIAL_BACKOFF;
+        loop {
+            let attempt = async {
+                let client = redis::Client::open(self.inner.url.as_str())?;
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(Some(self.inner.connect_timeout))
+                    // Requests carry their own deadline (`timeouts.command_ms`);
+                    // the client default of 500ms would cut off blocking commands
+                    .set_response_timeout(None);
+                let connects = (0..self.inner.pool_size)
+                    .map(|_| client.get_connection_manager_with_config(config.clone()));
+                match timeout(
+                    self.inner.connect_timeout,
+                    futures::future::try_join_all(connects),
+                )
+                .await
+                {
+                    Ok(pool) => Ok(pool?),
+                    Err(_) => anyhow::bail!("timeout after {:?}", self.inner.connect_timeout),
+                }
+            };
+            match attempt.await {
+                Ok(pool) => {
+                    tracing::info!(
+                        url = %redact_url(&self.inner.url),
+                        connections = pool.len(),
+                        "connected to Redis"
+                    );
+                    self.set_status(UpstreamStatus::Connected, None);
+                    self.inner.pool.send_replace(pool.clone());
+                    return pool;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        url = %redact_url(&self.inner.url),
+                        error = %e,
+                        retry_in_ms = delay.as_millis() as u64,
+                        "failed to connect to Redis"
+                    );
+                    self.set_status(UpstreamStatus::Connecting, Some(e.to_string()));
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an error means Redis could not be reached, as opposed to Redis
+/// replying with an error
+pub fn is_unavailable(e: &anyhow::Error) -> bool {
+    if e.is::<UpstreamUnavailable>() {
+        return true;
+    }
+    match e.downcast_ref::<redis::RedisError>() {
+        Some(re) => re.is_io_error() || re.is_connection_dropped() || re.is_connection_refusal(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nothing listens on port 1, so every connect is refused at once
+    const UNREACHABLE: &str = "redis://127.0.0.1:1";
+
+    #[tokio::test]
+    async fn commands_fail_fast_before_connecting() {
+        let upstream = Upstream::new(UNREACHABLE, Duration::from_millis(100), 2);
+        assert_eq!(upstream.status(), UpstreamStatus::Connecting);
+        let e = upstream.connection().unwrap_err();
+        assert!(is_unavailable(&e));
+        assert_eq!(
+            e.to_string(),
+            "Redis is not reachable at redis://127.0.0.1:1"
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_retrying_until_closed() {
+        let upstream = Upstream::new(UNREACHABLE, Duration::from_millis(100), 1);
+        upstream.spawn();
+        // Long enough for the first attempt and one retry after INITIAL_BACKOFF
+        tokio::time::sleep(INITIAL_BACKOFF * 2).await;
+        assert_eq!(upstream.status(), UpstreamStatus::Connecting);
+        assert!(upstream.last_error().is_some());
+        assert!(upstream.connection().is_err());
+
+        upstream.close();
+        assert_eq!(upstream.status(), UpstreamStatus::Closed);
+        assert!(upstream.inner.task.lock().unwrap().is_none());
+    }
+}
@@ -6,6 +6,16 @@ use axum::{
 };
 use base64::{engine::general_purpose::STANDARD as B64, Engine};
 
+/// Hide the password in a Redis URL so it can be shown in responses
+pub fn redact_url(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme_end), Some(at)) if at > scheme_end => {
+            format!("{}://***{}", &url[..scheme_end], &url[at..])
+        }
+        _ => url.to_string(),
+    }
+}
+
 pub fn is_object(v: &serde_json::Value) -> bool {
     v.is_object()
 }
@@ -20,7 +30,8 @@ pub fn is_result_array(v: &serde_json::Value) -> bool {
     false
 }
 
-fn encode_result_value(v: serde_json::Value) -> serde_json::Value {
+/// Base64-encode the strings in a result, for `upstash-encoding: base64`
+pub fn encode_result_value(v: serde_json::Value) -> serde_json::Value {
     match v {
         serde_json::Value::String(s) => serde_json::Value::String(B64.encode(s.as_bytes())),
         serde_json::Value::Array(a) => {
@@ -88,17 +99,24 @@ fn encode_response(mut resp: EnvResp) -> EnvResp {
     }
 }
 
-pub fn write_resp(resp: EnvResp, encoding: bool) -> Response {
-    let mut r = resp;
-    let status = match r.status.as_str() {
+/// HTTP status for an `EnvResp` status
+pub fn status_code(status: &str) -> StatusCode {
+    match status {
         "ok" => StatusCode::OK,
         "not_found" => StatusCode::NOT_FOUND,
         "malformed_data" => StatusCode::BAD_REQUEST,
         "redis_error" | "error" => StatusCode::BAD_REQUEST,
         "not_authorized" => StatusCode::UNAUTHORIZED,
+        "forbidden" => StatusCode::FORBIDDEN,
+        "rate_limited" => StatusCode::TOO_MANY_REQUESTS,
         "connection_error" => StatusCode::INTERNAL_SERVER_ERROR,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
-    };
+    }
+}
+
+pub fn write_resp(resp: EnvResp, encoding: bool) -> Response {
+    let mut r = resp;
+    let status = status_code(&r.status);
     if r.status == "ok" && encoding {
         r = encode_response(r);
     }
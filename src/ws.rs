@@ -0,0 +1,357 @@
+use crate::handlers::{error_status, routed_call};
+use crate::hub::{Signal, Subscription, Topic};
+use crate::metrics::{command_label, WsGuard, METRICS};
+use crate::models::AppState;
+use crate::policy::{Denied, Principal};
+use crate::utils::{encode_result_value, status_code};
+use axum::extract::ws::{close_code, CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::Extension;
+use futures::stream::SplitSink;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+/// Commands one session may have running at once; further frames are
+/// refused until some complete
+const MAX_IN_FLIGHT: usize = 64;
+
+/// A client frame: a command in the same format as `POST /`, tagged with an
+/// optional correlation ID that is echoed in the reply
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Frame {
+    #[serde(default)]
+    id: Value,
+    command: Value,
+}
+
+pub async fn get_ws(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Arc<Principal>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let enc = headers
+        .get("upstash-encoding")
+        .and_then(|v| v.to_str().ok())
+        == Some("base64");
+    // Kept to authenticate every frame again, so reloads reach open sockets
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(String::from);
+    ws.max_message_size(state.config.limits.max_body_bytes)
+        .on_upgrade(move |socket| async move {
+            Session::new(state, principal, token, enc).run(socket).await
+        })
+}
+
+fn reply_ok(id: Value, result: Value) -> Value {
+    json!({"id": id, "result": result})
+}
+
+fn reply_err(id: Value, status: &str, error: impl Into<String>) -> Value {
+    json!({"id": id, "status": status, "error": error.into()})
+}
+
+fn denied_reply(id: Value, denied: &Denied) -> Value {
+    let mut reply = match denied {
+        Denied::RateLimited(_) => reply_err(id, "rate_limited", denied.to_string()),
+        _ => reply_err(id, "forbidden", denied.to_string()),
+    };
+    if let Denied::RateLimited(wait) = denied {
+        reply["retry_after"] = json!(wait.as_secs_f64().ceil().max(1.0) as u64);
+    }
+    reply
+}
+
+/// Command arguments from a JSON array, as accepted by `POST /`
+fn parse_command(v: &Value) -> Result<Vec<String>, &'static str> {
+    let arr = v.as_array().filter(|a| !a.is_empty()).ok_or(
+        "Invalid command array. Expected a string array at root of the command and its arguments.",
+    )?;
+    arr.iter()
+        .map(|v| match v {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(n) => Ok(n.to_string()),
+            Value::Bool(b) => Ok(b.to_string()),
+            Value::Null => Ok(String::new()),
+            _ => Err("Invalid command array. Expected strings, numbers, or booleans."),
+        })
+        .collect()
+}
+
+fn text(v: &Value) -> Message {
+    Message::Text(Utf8Bytes::from(v.to_string()))
+}
+
+/// One `/ws` connection. Commands run concurrently on the shared pool and
+/// their replies arrive in completion order; subscriptions go through the
+/// shared pub/sub hub.
+struct Session {
+    state: AppState,
+    /// Resolved from `token` again for every frame
+    principal: Arc<Principal>,
+    token: Option<String>,
+    enc: bool,
+    sub: Subscription,
+    commands: JoinSet<Value>,
+}
+
+impl Session {
+    fn new(state: AppState, principal: Arc<Principal>, token: Option<String>, enc: bool) -> Self {
+        Session {
+            sub: state.hub.subscription(),
+            state,
+            principal,
+            token,
+            enc,
+            commands: JoinSet::new(),
+        }
+    }
+
+    async fn run(mut self, socket: WebSocket) {
+        let _guard = WsGuard::new();
+        let _hold = self.state.shutdown.hold();
+        let (mut tx, mut rx) = socket.split();
+        let shutdown = self.state.shutdown.clone();
+        loop {
+            let out = tokio::select! {
+                frame = rx.next() => match frame {
+                    Some(Ok(Message::Text(t))) => {
+                        // The token may have been revoked or changed by a reload
+                        match self.state.access.authenticate(self.token.as_deref()) {
+                            Some(principal) => self.principal = principal,
+                            None => {
+                                METRICS.auth_failures.inc();
+                                let _ = tx.send(close(close_code::POLICY, "token no longer valid")).await;
+                                break;
+                            }
+                        }
+                        self.handle(t.as_str()).await
+                    }
+                    Some(Ok(Message::Binary(_))) => Some(reply_err(
+                        Value::Null,
+                        "malformed_data",
+                        "Binary frames are not supported.",
+                    )),
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // Pings are answered by the socket itself
+                    Some(Ok(_)) => None,
+                },
+                Some(done) = self.commands.join_next(), if !self.commands.is_empty() => done.ok(),
+                msg = self.sub.next() => match msg {
+                    Some(Signal::Message(msg)) => Some(msg.message(self.enc).to_json()),
+                    Some(Signal::Reconnecting) => Some(json!({"type": "reconnecting"})),
+                    Some(Signal::Resubscribed) => Some(json!({"type": "resubscribed"})),
+                    Some(Signal::Lagged { missed }) => Some(json!({"type": "lagged", "missed": missed})),
+                    None => {
+                        let _ = tx.send(close(close_code::ERROR, "pub/sub connection closed")).await;
+                        break;
+                    }
+                },
+                _ = shutdown.wait() => {
+                    self.drain(&mut tx).await;
+                    let _ = tx.send(close(close_code::RESTART, "server shutting down")).await;
+                    break;
+                }
+            };
+            if let Some(out) = out {
+                if tx.send(text(&out)).await.is_err() {
+                    break;
+                }
+            }
+        }
+        self.commands.abort_all();
+    }
+
+    /// Give commands still running up to the command timeout to finish and
+    /// send their replies, so a restart doesn't drop them. Whatever is left
+    /// after that is aborted with the session.
+    async fn drain(&mut self, tx: &mut SplitSink<WebSocket, Message>) {
+        let replies = async {
+            while let Some(done) = self.commands.join_next().await {
+                let Ok(reply) = done else { continue };
+                if tx.send(text(&reply)).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = timeout(self.state.config.timeouts.command(), replies).await;
+    }
+
+    /// Handle one text frame, returning an immediate reply if there is one.
+    /// Regular commands reply later through `commands`.
+    async fn handle(&mut self, frame: &str) -> Option<Value> {
+        let frame: Frame = match serde_json::from_str(frame) {
+            Ok(f) => f,
+            Err(e) => {
+                return Some(reply_err(
+                    Value::Null,
+                    "malformed_data",
+                    format!("Invalid frame: {}", e),
+                ))
+            }
+        };
+        let id = frame.id;
+        let cmd = match parse_command(&frame.command) {
+            Ok(cmd) => cmd,
+            Err(e) => return Some(reply_err(id, "malformed_data", e)),
+        };
+        let name = cmd[0].to_ascii_uppercase();
+        let is_unsubscribe = matches!(
+            name.as_str(),
+            "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "SUNSUBSCRIBE"
+        );
+        // Leaving a channel is always allowed
+        if !is_unsubscribe {
+            let cmds = std::slice::from_ref(&cmd);
+            if let Err(denied) =
+                self.state
+                    .access
+                    .check_subscribe(&self.principal, &self.state.commands, cmds)
+            {
+                let reply = denied_reply(id, &denied);
+                let status = status_code(reply["status"].as_str().unwrap_or_default());
+                METRICS.observe_request("/ws", &command_label(Some(&name)), status, Duration::ZERO);
+                return Some(reply);
+            }
+        }
+        match name.as_str() {
+            "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE"
+            | "SUNSUBSCRIBE" => {
+                let start = Instant::now();
+                let reply = match self.pubsub_command(&name, cmd[1..].to_vec()).await {
+                    Ok(count) => reply_ok(id, json!(count)),
+                    Err((status, e)) => reply_err(id, &status, e),
+                };
+                let status = reply["status"].as_str().unwrap_or("ok");
+                METRICS.observe_request(
+                    "/ws",
+                    &command_label(Some(&name)),
+                    status_code(status),
+                    start.elapsed(),
+                );
+                Some(reply)
+            }
+            _ if self.commands.len() >= MAX_IN_FLIGHT => {
+                let reply = reply_err(
+                    id,
+                    "rate_limited",
+                    format!(
+                        "Too many commands in flight; at most {} may run at once.",
+                        MAX_IN_FLIGHT
+                    ),
+                );
+                METRICS.observe_request(
+                    "/ws",
+                    &command_label(Some(&name)),
+                    status_code("rate_limited"),
+                    Duration::ZERO,
+                );
+                Some(reply)
+            }
+            _ => {
+                let state = self.state.clone();
+                let enc = self.enc;
+                self.commands.spawn(async move {
+                    let start = Instant::now();
+                    let label = command_label(Some(&name));
+                    let (status, reply) = match routed_call(&state, cmd).await {
+                        Ok(v) => {
+                            let v = if enc { encode_result_value(v) } else { v };
+                            ("ok".to_string(), reply_ok(id, v))
+                        }
+                        Err(e) => {
+                            let status = error_status(&e);
+                            let reply = reply_err(id, &status, e.to_string());
+                            (status, reply)
+                        }
+                    };
+                    METRICS.observe_request("/ws", &label, status_code(&status), start.elapsed());
+                    reply
+                });
+                None
+            }
+        }
+    }
+
+    /// Apply a (un)subscribe to the session's subscription, returning how
+    /// many channels and patterns it holds afterwards
+    async fn pubsub_command(
+        &mut self,
+        name: &str,
+        args: Vec<String>,
+    ) -> Result<usize, (String, String)> {
+        if !self.state.config.features.pubsub {
+            return Err((
+                "forbidden".into(),
+                "Pub/sub is disabled on this server.".into(),
+            ));
+        }
+        let topic: fn(String) -> Topic = match name {
+            "SUBSCRIBE" | "UNSUBSCRIBE" => Topic::Channel,
+            "SSUBSCRIBE" | "SUNSUBSCRIBE" => Topic::Shard,
+            _ => Topic::Pattern,
+        };
+        match name {
+            "SUBSCRIBE" | "PSUBSCRIBE" | "SSUBSCRIBE" => {
+                if args.is_empty() {
+                    return Err((
+                        "malformed_data".into(),
+                        format!("{} needs at least one channel or pattern.", name),
+                    ));
+                }
+                for arg in args {
+                    self.sub.add(topic(arg)).await.map_err(|e| {
+                        (
+                            error_status(&e),
+                            format!("Failed to {}: {}", name.to_ascii_lowercase(), e),
+                        )
+                    })?;
+                }
+            }
+            _ if args.is_empty() => {
+                // Without arguments, leave every channel (or pattern)
+                let all: Vec<Topic> = self
+                    .sub
+                    .topics()
+                    .filter(|t| {
+                        matches!(
+                            (t, name),
+                            (Topic::Channel(_), "UNSUBSCRIBE")
+                                | (Topic::Pattern(_), "PUNSUBSCRIBE")
+                                | (Topic::Shard(_), "SUNSUBSCRIBE")
+                        )
+                    })
+                    .cloned()
+                    .collect();
+                for t in &all {
+                    self.sub.remove(t);
+                }
+            }
+            _ => {
+                for arg in args {
+                    self.sub.remove(&topic(arg));
+                }
+            }
+        }
+        Ok(self.sub.len())
+    }
+}
+
+fn close(code: u16, reason: &'static str) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: Utf8Bytes::from_static(reason),
+    }))
+}
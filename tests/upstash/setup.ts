@@ -8,6 +8,68 @@ export const redis = new Redis({
   token: token,
 })
 
+// Extra tokens from server.toml
+export const tokens = {
+  readOnly: "test-read-only-token",
+  prefixed: "test-prefixed-token",
+  news: "test-news-token",
+}
+
+/** Send one command over REST with `token` */
+export const call = async (command: unknown[], token = process.env.SR_TOKEN) => {
+  const res = await fetch(url!, {
+    method: "POST",
+    headers: { Authorization: `Bearer ${token}`, "Content-Type": "application/json" },
+    body: JSON.stringify(command),
+  })
+  return { status: res.status, body: await res.json() as any }
+}
+
+export type SseEvent = { event?: string, data: string, id?: string }
+
+/**
+ * Open an SSE stream at `path`, collecting its events until `close()`. Resolves
+ * once the response headers arrive; a non-2xx `status` has no events.
+ */
+export const sse = async (path: string, token = process.env.SR_TOKEN, init: RequestInit = {}) => {
+  const abort = new AbortController()
+  const res = await fetch(new URL(path, url), {
+    ...init,
+    headers: { Authorization: `Bearer ${token}`, ...init.headers },
+    signal: abort.signal,
+  })
+  const events: SseEvent[] = []
+  const read = async () => {
+    const reader = res.body!.pipeThrough(new TextDecoderStream()).getReader()
+    let buf = ""
+    while (true) {
+      const { value, done } = await reader.read()
+      if (done) return
+      buf += value
+      let end
+      while ((end = buf.indexOf("\n\n")) >= 0) {
+        const ev: SseEvent = { data: "" }
+        const data: string[] = []
+        for (const line of buf.slice(0, end).split("\n")) {
+          const i = line.indexOf(":")
+          if (i <= 0) continue
+          const field = line.slice(0, i)
+          const value = line.slice(i + 1).replace(/^ /, "")
+          if (field === "data") data.push(value)
+          else if (field === "event") ev.event = value
+          else if (field === "id") ev.id = value
+        }
+        buf = buf.slice(end + 2)
+        if (data.length > 0 || ev.event) events.push({ ...ev, data: data.join("\n") })
+      }
+    }
+  }
+  if (res.ok) read().catch(() => {})
+  return { status: res.status, events, close: () => abort.abort() }
+}
+
+export const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms))
+
 export const cleanup = async () => {
   await redis.flushall()
 }
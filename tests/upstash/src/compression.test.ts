@@ -0,0 +1,38 @@
+import { expect, it, describe } from 'bun:test';
+
+const url = process.env.SR_URL
+
+const pipeline = (body: BodyInit, headers: Record<string, string>) =>
+  fetch(new URL("/pipeline", url), {
+    method: "POST",
+    headers: {
+      Authorization: `Bearer ${process.env.SR_TOKEN}`,
+      "Content-Type": "application/json",
+      ...headers,
+    },
+    body,
+  })
+
+describe("Compression", () => {
+  const big = "x".repeat(4096)
+
+  it("should accept a gzip-compressed pipeline and compress the response", async () => {
+    const body = Bun.gzipSync(JSON.stringify([["SET", "compression:big", big], ["GET", "compression:big"]]))
+    const res = await pipeline(body, { "Content-Encoding": "gzip", "Accept-Encoding": "gzip" })
+    expect(res.status).toBe(200)
+    expect(res.headers.get("content-encoding")).toBe("gzip")
+    const results = await res.json() as any[]
+    expect(results.map((r) => r.result)).toEqual(["OK", big])
+  });
+
+  it("should send small responses uncompressed", async () => {
+    const res = await pipeline(JSON.stringify([["PING"]]), { "Accept-Encoding": "gzip" })
+    expect(res.status).toBe(200)
+    expect(res.headers.get("content-encoding")).toBeNull()
+  });
+
+  it("should refuse unsupported request encodings", async () => {
+    const res = await pipeline(JSON.stringify([["PING"]]), { "Content-Encoding": "deflate" })
+    expect(res.status).toBe(415)
+  });
+});
@@ -0,0 +1,52 @@
+import { expect, it, describe } from 'bun:test';
+
+const url = process.env.SR_URL
+
+// Allowed in server.toml
+const origin = "http://app.test"
+
+const preflight = (path: string, from = origin) =>
+  fetch(new URL(path, url), {
+    method: "OPTIONS",
+    headers: {
+      Origin: from,
+      "Access-Control-Request-Method": "POST",
+      "Access-Control-Request-Headers": "authorization, content-type, upstash-encoding",
+    },
+  })
+
+describe("CORS", () => {
+  it("should answer preflights without a token", async () => {
+    const res = await preflight("/")
+    expect(res.status).toBe(200)
+    expect(res.headers.get("access-control-allow-origin")).toBe(origin)
+    const headers = res.headers.get("access-control-allow-headers")!.split(",")
+    expect(headers).toContain("authorization")
+    expect(headers).toContain("upstash-encoding")
+    expect(res.headers.get("access-control-allow-credentials")).toBeNull()
+  });
+
+  it("should allow credentials on every streaming route", async () => {
+    for (const path of ["/subscribe/cors", "/psubscribe/cors.*", "/ssubscribe/cors", "/keyspace/cors:*", "/xreadgroup/g/c/cors", "/ws"]) {
+      const res = await preflight(path)
+      expect(res.status).toBe(200)
+      expect(res.headers.get("access-control-allow-origin")).toBe(origin)
+      expect(res.headers.get("access-control-allow-credentials")).toBe("true")
+    }
+  });
+
+  it("should not allow other origins", async () => {
+    const res = await preflight("/", "http://other.test")
+    expect(res.headers.get("access-control-allow-origin")).toBeNull()
+  });
+
+  it("should send CORS headers on errors", async () => {
+    const res = await fetch(new URL("/", url), {
+      method: "POST",
+      headers: { Origin: origin, "Content-Type": "application/json" },
+      body: JSON.stringify(["PING"]),
+    })
+    expect(res.status).toBe(401)
+    expect(res.headers.get("access-control-allow-origin")).toBe(origin)
+  });
+});
@@ -0,0 +1,71 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+const messages = (events: { data: string, id?: string }[]) =>
+  events.filter((e) => e.data.startsWith("message,"))
+
+describe("Resumable Streams", () => {
+  it("should replay messages published while away", async () => {
+    const first = await sse("/subscribe/durable-one?durable=true")
+    expect(first.status).toBe(200)
+    await sleep(500)
+    await call(["PUBLISH", "durable-one", "m1"])
+    await sleep(500)
+    first.close()
+
+    const seen = messages(first.events)
+    expect(seen.map((e) => e.data)).toEqual(['message,durable-one,"m1"'])
+    expect(seen[0].id).toMatch(/^\d+-\d+$/)
+
+    await call(["PUBLISH", "durable-one", "m2"])
+    await call(["PUBLISH", "durable-one", "m3"])
+
+    const resumed = await sse("/subscribe/durable-one?durable=true", undefined, {
+      headers: { "Last-Event-ID": seen[0].id! },
+    })
+    await sleep(500)
+    resumed.close()
+    expect(messages(resumed.events).map((e) => e.data)).toEqual([
+      'message,durable-one,"m2"',
+      'message,durable-one,"m3"',
+    ])
+  }, 10000);
+
+  it("should track each channel of a multi-channel stream", async () => {
+    const first = await sse("/subscribe/durable-b/durable-a?durable=true")
+    await sleep(500)
+    await call(["PUBLISH", "durable-a", "a1"])
+    await call(["PUBLISH", "durable-b", "b1"])
+    await sleep(500)
+    first.close()
+
+    const seen = messages(first.events)
+    expect(seen).toHaveLength(2)
+    // One entry ID per channel, in channel name order
+    const last = seen[1].id!
+    expect(last.split(",")).toHaveLength(2)
+
+    await call(["PUBLISH", "durable-b", "b2"])
+    const resumed = await sse("/subscribe/durable-a/durable-b?durable=true", undefined, {
+      headers: { "Last-Event-ID": last },
+    })
+    await sleep(500)
+    resumed.close()
+    expect(messages(resumed.events).map((e) => e.data)).toEqual(['message,durable-b,"b2"'])
+  }, 10000);
+
+  it("should refuse an ID for a different set of channels", async () => {
+    const stream = await sse("/subscribe/durable-a/durable-b?durable=true", undefined, {
+      headers: { "Last-Event-ID": "1-0" },
+    })
+    expect(stream.status).toBe(400)
+  });
+
+  it("should keep recording after the script cache is flushed", async () => {
+    await call(["SCRIPT", "FLUSH"])
+    const { body } = await call(["PUBLISH", "durable-flushed", "x"])
+    expect(body.result).toBe(0)
+    const { body: len } = await call(["XLEN", "sr:durable:durable-flushed"])
+    expect(len.result).toBe(1)
+  });
+});
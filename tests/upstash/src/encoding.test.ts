@@ -0,0 +1,56 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+describe("Payload Encoding", () => {
+  it("should keep base64 payloads unnamed in the Upstash format", async () => {
+    const stream = await sse("/subscribe/enc-upstash?encoding=base64")
+    expect(stream.status).toBe(200)
+    await sleep(500)
+    await call(["PUBLISH", "enc-upstash", "hi"])
+    await sleep(500)
+    stream.close()
+
+    // Named events would be dropped by EventSource.onmessage
+    const message = stream.events.find((e) => e.data.startsWith("message,"))
+    expect(message).toEqual({ data: 'message,enc-upstash,"aGk="' })
+  }, 10000);
+
+  it("should keep base64 payloads unnamed with upstash-encoding", async () => {
+    const stream = await sse("/subscribe/enc-header", undefined, {
+      headers: { "upstash-encoding": "base64" },
+    })
+    await sleep(500)
+    await call(["PUBLISH", "enc-header", "hi"])
+    await sleep(500)
+    stream.close()
+
+    const message = stream.events.find((e) => e.data.startsWith("message,"))
+    expect(message).toEqual({ data: 'message,enc-header,"aGk="' })
+  }, 10000);
+
+  it("should leave text payloads unnamed in the Upstash format", async () => {
+    const stream = await sse("/subscribe/enc-text")
+    await sleep(500)
+    await call(["PUBLISH", "enc-text", "hi"])
+    await sleep(500)
+    stream.close()
+
+    const message = stream.events.find((e) => e.data.startsWith("message,"))
+    expect(message).toEqual({ data: 'message,enc-text,"hi"' })
+  }, 10000);
+
+  it("should flag base64 payloads in the JSON format", async () => {
+    const stream = await sse("/subscribe/enc-json?format=json", undefined, {
+      headers: { "upstash-encoding": "base64" },
+    })
+    await sleep(500)
+    await call(["PUBLISH", "enc-json", "hi"])
+    await sleep(500)
+    stream.close()
+
+    const message = stream.events.find((e) => e.event === "message")
+    expect(JSON.parse(message!.data)).toEqual({
+      type: "message", channel: "enc-json", payload: "aGk=", encoding: "base64",
+    })
+  }, 10000);
+});
@@ -0,0 +1,71 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+const parsed = (events: { event?: string, data: string }[]) =>
+  events.map((e) => [e.event, JSON.parse(e.data)])
+
+describe("JSON Event Format", () => {
+  it("should name events after the message type", async () => {
+    const stream = await sse("/subscribe/format,a?format=json")
+    expect(stream.status).toBe(200)
+    await sleep(500)
+    await call(["PUBLISH", "format,a", '{"id":1}'])
+    await call(["PUBLISH", "format,a", "plain, text"])
+    await sleep(500)
+    stream.close()
+
+    // Commas in channel names and payloads stay unambiguous
+    expect(parsed(stream.events)).toEqual([
+      ["subscribe", { type: "subscribe", channel: "format,a", count: 1 }],
+      ["message", { type: "message", channel: "format,a", payload: '{"id":1}' }],
+      ["message", { type: "message", channel: "format,a", payload: "plain, text" }],
+    ])
+  }, 10000);
+
+  it("should keep payloads that look like JSON distinct", async () => {
+    const stream = await sse("/subscribe/format-distinct?format=json")
+    await sleep(500)
+    for (const payload of ['"hello"', "hello", "123"]) {
+      await call(["PUBLISH", "format-distinct", payload])
+    }
+    await sleep(500)
+    stream.close()
+
+    const payloads = stream.events
+      .filter((e) => e.event === "message")
+      .map((e) => JSON.parse(e.data).payload)
+    expect(payloads).toEqual(['"hello"', "hello", "123"])
+    expect(new Set(payloads).size).toBe(3)
+  }, 10000);
+
+  it("should carry the pattern on pmessage events", async () => {
+    const stream = await sse("/psubscribe/format-p,*?format=json")
+    await sleep(500)
+    await call(["PUBLISH", "format-p,b", "hi"])
+    await sleep(500)
+    stream.close()
+
+    expect(parsed(stream.events)).toEqual([
+      ["psubscribe", { type: "psubscribe", pattern: "format-p,*", count: 1 }],
+      ["pmessage", { type: "pmessage", pattern: "format-p,*", channel: "format-p,b", payload: "hi" }],
+    ])
+  }, 10000);
+
+  it("should keep the Upstash format by default", async () => {
+    const stream = await sse("/subscribe/format-default")
+    await sleep(500)
+    await call(["PUBLISH", "format-default", "hi"])
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events).toEqual([
+      { data: "subscribe,format-default,1" },
+      { data: 'message,format-default,"hi"' },
+    ])
+  }, 10000);
+
+  it("should refuse unknown formats", async () => {
+    const stream = await sse("/subscribe/format-x?format=xml")
+    expect(stream.status).toBe(400)
+  });
+});
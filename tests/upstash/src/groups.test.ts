@@ -0,0 +1,89 @@
+import { expect, it, describe, beforeEach } from 'bun:test';
+import { call, cleanup, sleep, sse, tokens } from '../setup';
+
+const url = process.env.SR_URL
+
+const post = async (path: string, body: unknown, token = process.env.SR_TOKEN) => {
+  const res = await fetch(new URL(path, url), {
+    method: "POST",
+    headers: { Authorization: `Bearer ${token}`, "Content-Type": "application/json" },
+    body: JSON.stringify(body),
+  })
+  return { status: res.status, body: await res.json() as any }
+}
+
+const pending = async (path: string) => {
+  const res = await fetch(new URL(path, url), {
+    headers: { Authorization: `Bearer ${process.env.SR_TOKEN}` },
+  })
+  return (await res.json() as any).result
+}
+
+const entries = (events: { data: string }[]) => events.map((e) => JSON.parse(e.data))
+
+beforeEach(cleanup);
+
+describe("Consumer Groups", () => {
+  it("should stream new entries to a consumer", async () => {
+    const stream = await sse("/xreadgroup/g/c1/groups:orders?create=true&block_ms=200")
+    expect(stream.status).toBe(200)
+    await sleep(500)
+    const { body } = await call(["XADD", "groups:orders", "*", "sku", "A1"])
+    await sleep(800)
+    stream.close()
+
+    expect(entries(stream.events)).toEqual([
+      { stream: "groups:orders", id: body.result, fields: { sku: "A1" } },
+    ])
+    expect(stream.events[0].id).toBe(body.result)
+  }, 10000);
+
+  it("should refuse a missing group before streaming", async () => {
+    await call(["XADD", "groups:missing", "*", "sku", "A1"])
+    const stream = await sse("/xreadgroup/nogroup/c1/groups:missing")
+    expect(stream.status).toBe(400)
+  });
+
+  it("should keep entries pending until acknowledged", async () => {
+    const first = await sse("/xreadgroup/g/c1/groups:ack?create=true&block_ms=200")
+    await sleep(500)
+    const { body } = await call(["XADD", "groups:ack", "*", "sku", "A1"])
+    await sleep(800)
+    first.close()
+
+    const listed = await pending("/xpending/groups:ack/g")
+    expect(listed).toHaveLength(1)
+    expect(listed[0]).toMatchObject({ id: body.result, consumer: "c1", deliveries: 1 })
+
+    // Reconnecting with ?pending=true replays the unacknowledged entry
+    const again = await sse("/xreadgroup/g/c1/groups:ack?pending=true&block_ms=200")
+    await sleep(800)
+    again.close()
+    expect(entries(again.events).map((e) => e.id)).toEqual([body.result])
+
+    const ack = await post("/xack/groups:ack/g", [body.result])
+    expect(ack.body.result).toBe(1)
+    expect(await pending("/xpending/groups:ack/g")).toEqual([])
+  }, 10000);
+
+  it("should claim stale entries for another consumer", async () => {
+    const stream = await sse("/xreadgroup/g/c1/groups:claim?create=true&block_ms=200")
+    await sleep(500)
+    const { body } = await call(["XADD", "groups:claim", "*", "sku", "A1"])
+    await sleep(800)
+    stream.close()
+
+    const claimed = await post("/xclaim/groups:claim/g/c2", { min_idle_ms: 0, ids: [body.result] })
+    expect(claimed.body.result).toEqual([{ id: body.result, fields: { sku: "A1" } }])
+    const listed = await pending("/xpending/groups:claim/g")
+    expect(listed[0]).toMatchObject({ consumer: "c2", deliveries: 2 })
+  }, 10000);
+
+  it("should check the token's policy", async () => {
+    await call(["XGROUP", "CREATE", "groups:policy", "g", "$", "MKSTREAM"])
+    const stream = await sse("/xreadgroup/g/c1/groups:policy", tokens.readOnly)
+    expect(stream.status).toBe(403)
+    const ack = await post("/xack/groups:policy/g", ["0-1"], tokens.readOnly)
+    expect(ack.status).toBe(403)
+  });
+});
@@ -0,0 +1,40 @@
+import { expect, it, describe } from 'bun:test';
+
+const url = process.env.SR_URL
+
+describe("Health Probes", () => {
+  it("should report liveness without a token", async () => {
+    const res = await fetch(`${url}/healthz`);
+    expect(res.status).toBe(200);
+    const body = await res.json();
+    expect(body.status).toBe("ok");
+  });
+
+  it("should report readiness without a token", async () => {
+    const res = await fetch(`${url}/readyz`);
+    expect(res.status).toBe(200);
+    const body = await res.json();
+    expect(body.status).toBe("ok");
+    expect(body.redis.status).toBe("ok");
+    expect(body.pubsub.status).toBe("ok");
+    expect(body.pubsub.reconnecting).toBe(0);
+    // Public probes don't name hosts
+    expect(body.pubsub.error).toBeUndefined();
+    expect(body.replicas).toBeUndefined();
+  });
+
+  it("should not keep a pub/sub connection per probe", async () => {
+    const before = (await (await fetch(`${url}/readyz`)).json()).pubsub.connected;
+    for (let i = 0; i < 5; i++) await fetch(`${url}/readyz`);
+    const after = (await (await fetch(`${url}/readyz`)).json()).pubsub.connected;
+    expect(after).toBe(before);
+  });
+
+  it("should still require a token for commands", async () => {
+    const res = await fetch(url!, {
+      method: "POST",
+      body: JSON.stringify(["PING"]),
+    });
+    expect(res.status).toBe(401);
+  });
+});
@@ -0,0 +1,66 @@
+import { expect, it, describe } from 'bun:test';
+import http2 from 'node:http2';
+import { call, sleep } from '../setup';
+
+const url = process.env.SR_URL!
+const auth = `Bearer ${process.env.SR_TOKEN}`
+
+/** Send one request over an h2c session, resolving with its status and body */
+const request = (session: http2.ClientHttp2Session, path: string, body?: string) =>
+  new Promise<{ status: number, body: string }>((resolve, reject) => {
+    const req = session.request({
+      ":method": body === undefined ? "GET" : "POST",
+      ":path": path,
+      authorization: auth,
+      "content-type": "application/json",
+    })
+    let status = 0
+    let data = ""
+    req.setEncoding("utf8")
+    req.on("response", (headers) => { status = Number(headers[":status"]) })
+    req.on("data", (chunk) => { data += chunk })
+    req.on("end", () => resolve({ status, body: data }))
+    req.on("error", reject)
+    req.end(body)
+  })
+
+describe("HTTP/2", () => {
+  it("should multiplex commands over one h2c connection", async () => {
+    // Cleartext HTTP/2 with prior knowledge
+    const session = http2.connect(url)
+    try {
+      const replies = await Promise.all(
+        Array.from({ length: 20 }, (_, i) => request(session, "/", JSON.stringify(["ECHO", `h2-${i}`]))),
+      )
+      replies.forEach((reply, i) => {
+        expect(reply.status).toBe(200)
+        expect(JSON.parse(reply.body)).toEqual({ result: `h2-${i}` })
+      })
+    } finally {
+      session.close()
+    }
+  }, 10000);
+
+  it("should stream /subscribe over HTTP/2", async () => {
+    const session = http2.connect(url)
+    try {
+      const stream = session.request({ ":path": "/subscribe/h2-channel", authorization: auth })
+      let data = ""
+      stream.setEncoding("utf8")
+      stream.on("data", (chunk) => { data += chunk })
+      await sleep(500)
+
+      // A command on the same connection while the stream is open
+      const reply = await request(session, "/", JSON.stringify(["PING"]))
+      expect(JSON.parse(reply.body)).toEqual({ result: "PONG" })
+
+      await call(["PUBLISH", "h2-channel", "over h2"])
+      await sleep(500)
+      stream.close()
+      expect(data).toContain("data: subscribe,h2-channel,1")
+      expect(data).toContain('data: message,h2-channel,"over h2"')
+    } finally {
+      session.close()
+    }
+  }, 10000);
+});
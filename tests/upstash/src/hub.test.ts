@@ -0,0 +1,45 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+const messages = (events: { data: string }[]) =>
+  events.filter((e) => e.data.startsWith("message,")).map((e) => e.data)
+
+describe("Shared Subscriptions", () => {
+  it("should confirm every client joining a channel at once", async () => {
+    const streams = await Promise.all([1, 2, 3].map(() => sse("/subscribe/hub-shared")))
+    for (const stream of streams) expect(stream.status).toBe(200)
+    await sleep(500)
+
+    const { body } = await call(["PUBLISH", "hub-shared", "hello"])
+    // One Redis subscription serves all three clients
+    expect(body.result).toBe(1)
+    await sleep(500)
+    for (const stream of streams) {
+      stream.close()
+      expect(messages(stream.events)).toEqual(['message,hub-shared,"hello"'])
+    }
+  }, 10000);
+
+  it("should keep other clients subscribed when one leaves", async () => {
+    const first = await sse("/subscribe/hub-leave")
+    const second = await sse("/subscribe/hub-leave")
+    await sleep(500)
+    first.close()
+    await sleep(200)
+
+    await call(["PUBLISH", "hub-leave", "still here"])
+    await sleep(500)
+    second.close()
+    expect(messages(second.events)).toEqual(['message,hub-leave,"still here"'])
+  }, 10000);
+
+  it("should unsubscribe from Redis once the last client leaves", async () => {
+    const stream = await sse("/subscribe/hub-last")
+    await sleep(500)
+    stream.close()
+    await sleep(500)
+
+    const { body } = await call(["PUBSUB", "NUMSUB", "hub-last"])
+    expect(body.result).toEqual(["hub-last", 0])
+  }, 10000);
+});
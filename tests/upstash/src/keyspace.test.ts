@@ -0,0 +1,44 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse, tokens } from '../setup';
+
+describe("Keyspace Notifications", () => {
+  it("only streams keys under the token's prefixes", async () => {
+    const stream = await sse("/keyspace/*", tokens.prefixed)
+    expect(stream.status).toBe(200)
+    await sleep(500)
+
+    await call(["SET", "other:key", "1"])
+    await call(["SET", "app:key", "1"])
+    await sleep(500)
+    stream.close()
+
+    const keys = stream.events.map((e) => JSON.parse(e.data).key)
+    expect(keys).toContain("app:key")
+    expect(keys).not.toContain("other:key")
+  }, 10000);
+
+  it("denies notification channels on the generic routes", async () => {
+    for (const path of ["/psubscribe/__keyspace@0__:*", "/subscribe/__keyevent@0__:set", "/psubscribe/__key*", "/ssubscribe/__keyspace@0__:other:key"]) {
+      const stream = await sse(path, tokens.prefixed)
+      expect(stream.status).toBe(403)
+    }
+  }, 10000);
+
+  it("still lets prefixed tokens subscribe to other channels", async () => {
+    const stream = await sse("/subscribe/app-news", tokens.prefixed)
+    expect(stream.status).toBe(200)
+    stream.close()
+  }, 10000);
+});
+
+describe("Keyspace Databases", () => {
+  it("refuses databases other than the configured one", async () => {
+    const other = await sse("/keyspace/*?db=1")
+    expect(other.status).toBe(400)
+    other.close()
+
+    const configured = await sse("/keyspace/*?db=0")
+    expect(configured.status).toBe(200)
+    configured.close()
+  }, 10000);
+});
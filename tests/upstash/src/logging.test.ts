@@ -0,0 +1,43 @@
+import { expect, it, describe } from 'bun:test';
+
+const url = process.env.SR_URL
+
+const ping = (headers: Record<string, string> = {}) =>
+  fetch(url!, {
+    method: "POST",
+    headers: {
+      Authorization: `Bearer ${process.env.SR_TOKEN}`,
+      "Content-Type": "application/json",
+      ...headers,
+    },
+    body: JSON.stringify(["PING"]),
+  })
+
+describe("Request IDs", () => {
+  it("should echo the caller's X-Request-Id", async () => {
+    const res = await ping({ "X-Request-Id": "req-123" })
+    expect(res.headers.get("x-request-id")).toBe("req-123")
+  });
+
+  it("should assign one when none is sent", async () => {
+    const res = await ping()
+    expect(res.headers.get("x-request-id")).toMatch(/^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$/)
+  });
+
+  it("should replace IDs that are too long", async () => {
+    const res = await ping({ "X-Request-Id": "x".repeat(129) })
+    const id = res.headers.get("x-request-id")
+    expect(id).not.toBe("x".repeat(129))
+    expect(id).toHaveLength(36)
+  });
+
+  it("should tag error responses too", async () => {
+    const res = await fetch(url!, {
+      method: "POST",
+      headers: { "X-Request-Id": "req-unauthorized" },
+      body: JSON.stringify(["PING"]),
+    })
+    expect(res.status).toBe(401)
+    expect(res.headers.get("x-request-id")).toBe("req-unauthorized")
+  });
+});
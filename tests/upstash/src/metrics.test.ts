@@ -0,0 +1,55 @@
+import { expect, it, describe } from 'bun:test';
+import { call } from '../setup';
+
+const url = process.env.SR_URL
+
+const metrics = async () => {
+  const res = await fetch(`${url}/metrics`, {
+    headers: { Authorization: `Bearer ${process.env.SR_TOKEN}` },
+  });
+  expect(res.status).toBe(200);
+  return res.text();
+}
+
+describe("Metrics", () => {
+  it("should require a token", async () => {
+    const res = await fetch(`${url}/metrics`);
+    expect(res.status).toBe(401);
+  });
+
+  it("should count requests by route, command and status", async () => {
+    await call(["SET", "metrics:key", "1"]);
+    const text = await metrics();
+    expect(text).toMatch(/sr_http_requests_total\{[^}]*command="set"[^}]*route="\/"[^}]*status="200"[^}]*\} \d+/);
+    expect(text).toContain("sr_http_request_duration_seconds_bucket");
+    expect(text).toMatch(/sr_redis_roundtrip_seconds_count\{command="set"\} \d+/);
+  });
+
+  it("should label commands Redis doesn't know as unknown", async () => {
+    await call(["NOTACOMMAND", "x"]);
+    await call(["NOT_A_COMMAND_EITHER_123"]);
+    const text = await metrics();
+    expect(text).toContain('command="unknown"');
+    expect(text).not.toContain("notacommand");
+    expect(text).not.toContain("not_a_command_either_123");
+  });
+
+  it("should export the other series", async () => {
+    await fetch(url!, { method: "POST", body: JSON.stringify(["PING"]) });
+    const text = await metrics();
+    for (const name of [
+      "sr_pipeline_commands",
+      "sr_redis_timeouts_total",
+      "sr_redis_connection_errors_total",
+      "sr_sse_subscriptions_active",
+      "sr_sse_channels_active",
+      "sr_pubsub_topics_active",
+      "sr_pubsub_lagged_messages_total",
+      "sr_pubsub_reconnects_total",
+      "sr_websocket_sessions_active",
+      "sr_auth_failures_total",
+    ]) {
+      expect(text).toContain(name);
+    }
+  });
+});
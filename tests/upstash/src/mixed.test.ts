@@ -0,0 +1,51 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+const data = (events: { data: string }[]) => events.map((e) => e.data)
+
+describe("Mixed Subscriptions", () => {
+  it("should take channels and patterns as query parameters", async () => {
+    const stream = await sse("/subscribe?channel=mixed/a&channel=mixed-b&pattern=mixed.*")
+    expect(stream.status).toBe(200)
+    await sleep(500)
+    await call(["PUBLISH", "mixed/a", "direct"])
+    await call(["PUBLISH", "mixed.x", "matched"])
+    await sleep(500)
+    stream.close()
+
+    expect(data(stream.events)).toEqual([
+      "subscribe,mixed/a,1",
+      "subscribe,mixed-b,2",
+      "psubscribe,mixed.*,3",
+      'message,mixed/a,"direct"',
+      'pmessage,mixed.*,mixed.x,"matched"',
+    ])
+  }, 10000);
+
+  it("should take channels and patterns as a POST body", async () => {
+    const stream = await sse("/subscribe?format=json", undefined, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify({ channels: ["mixed-post/a"], patterns: ["mixed-post.*", "other-post.*"] }),
+    })
+    expect(stream.status).toBe(200)
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events.map((e) => [e.event, JSON.parse(e.data)])).toEqual([
+      ["subscribe", { type: "subscribe", channel: "mixed-post/a", count: 1 }],
+      ["psubscribe", { type: "psubscribe", pattern: "mixed-post.*", count: 2 }],
+      ["psubscribe", { type: "psubscribe", pattern: "other-post.*", count: 3 }],
+    ])
+  }, 10000);
+
+  it("should refuse an empty subscription", async () => {
+    const stream = await sse("/subscribe")
+    expect(stream.status).toBe(400)
+  });
+
+  it("should refuse durable streams with patterns", async () => {
+    const stream = await sse("/subscribe?channel=mixed-d&pattern=mixed-d.*&durable=true")
+    expect(stream.status).toBe(400)
+  });
+});
@@ -0,0 +1,87 @@
+import { expect, it, describe, beforeEach } from "bun:test";
+import { call, cleanup, sse, tokens } from "../setup";
+
+const url = process.env.SR_URL;
+
+beforeEach(cleanup);
+
+describe("Read-only Tokens", () => {
+  it("should allow reads", async () => {
+    await call(["SET", "policy:key", "v"]);
+    const { status, body } = await call(["GET", "policy:key"], tokens.readOnly);
+    expect(status).toBe(200);
+    expect(body.result).toBe("v");
+  });
+
+  it("should deny writes", async () => {
+    const { status } = await call(["SET", "policy:key", "v"], tokens.readOnly);
+    expect(status).toBe(403);
+  });
+
+  it("should refuse EVAL_RO, which is sent as EVAL", async () => {
+    const { status } = await call(
+      ["EVAL_RO", "return redis.call('SET', 'policy:k', 'v')", "0"],
+      tokens.readOnly,
+    );
+    expect(status).toBe(403);
+    const { body: check } = await call(["GET", "policy:k"]);
+    expect(check.result).toBeNull();
+  });
+
+  it("should refuse FCALL_RO and EVALSHA_RO", async () => {
+    for (const command of [["FCALL_RO", "f", "0"], ["EVALSHA_RO", "0".repeat(40), "0"]]) {
+      const { status } = await call(command, tokens.readOnly);
+      expect(status).toBe(403);
+    }
+  });
+});
+
+describe("Read-only Script Variants", () => {
+  it("should run EVAL_RO for other tokens", async () => {
+    await call(["SET", "policy:key", "v"]);
+    const { body } = await call(["EVAL_RO", "return redis.call('GET', KEYS[1])", "1", "policy:key"]);
+    expect(body.result).toBe("v");
+  });
+});
+
+describe("Channel-restricted Tokens", () => {
+  const publish = (channel: string) =>
+    fetch(new URL(`/publish/${channel}`, url), {
+      method: "POST",
+      headers: { Authorization: `Bearer ${tokens.news}` },
+      body: "hello",
+    });
+
+  it("should allow channels matching its globs", async () => {
+    const sub = await sse("/subscribe/news.sport", tokens.news);
+    expect(sub.status).toBe(200);
+    sub.close();
+    const res = await publish("news.sport");
+    expect(res.status).toBe(200);
+  });
+
+  it("should refuse subscribing to other channels", async () => {
+    const sub = await sse("/subscribe/other", tokens.news);
+    expect(sub.status).toBe(403);
+    sub.close();
+  });
+
+  it("should refuse publishing to other channels", async () => {
+    const res = await publish("other");
+    expect(res.status).toBe(403);
+  });
+});
+
+describe("Prefix-restricted Tokens", () => {
+  it("should refuse durable publishes whose stream is outside its prefixes", async () => {
+    // Durable mode records this under sr:durable:app:news
+    const { status, body } = await call(["PUBLISH", "app:news", "hello"], tokens.prefixed);
+    expect(status).toBe(403);
+    expect(body.error).toContain("sr:durable:app:news");
+  });
+
+  it("should still allow keys under its prefixes", async () => {
+    const { status } = await call(["SET", "app:key", "v"], tokens.prefixed);
+    expect(status).toBe(200);
+  });
+});
@@ -0,0 +1,29 @@
+import { expect, it, describe, beforeEach } from 'bun:test';
+import { cleanup, redis } from '../setup';
+
+beforeEach(cleanup);
+
+describe("Connection Pool", () => {
+  it("should run concurrent commands without losing any", async () => {
+    const results = await Promise.all(
+      Array.from({ length: 500 }, () => redis.incr("pool:counter")),
+    );
+    expect(new Set(results).size).toBe(500);
+    expect(await redis.get("pool:counter")).toBe(500);
+  }, 20000);
+
+  it("should keep each pipeline in order under concurrency", async () => {
+    const runs = await Promise.all(
+      Array.from({ length: 50 }, (_, i) => {
+        const p = redis.pipeline();
+        for (let j = 0; j < 10; j++) p.rpush(`pool:list:${i}`, j);
+        p.lrange(`pool:list:${i}`, 0, -1);
+        return p.exec();
+      }),
+    );
+    for (const run of runs) {
+      expect(run.slice(0, 10)).toEqual([1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+      expect(run[10]).toEqual([0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+  }, 20000);
+});
@@ -0,0 +1,86 @@
+import { expect, it, describe } from 'bun:test';
+import { sleep, sse } from '../setup';
+
+const url = process.env.SR_URL
+
+const publish = (path: string, body: BodyInit, headers: Record<string, string> = {}) =>
+  fetch(new URL(path, url), {
+    method: "POST",
+    headers: { Authorization: `Bearer ${process.env.SR_TOKEN}`, ...headers },
+    body,
+  })
+
+describe("Publish Endpoint", () => {
+  it("should publish the raw body to every channel", async () => {
+    const stream = await sse("/subscribe/raw-a/raw-b")
+    await sleep(500)
+
+    const res = await publish("/publish/raw-a/raw-b", '{"title":"hello"}', { "Content-Type": "text/plain" })
+    expect(res.status).toBe(200)
+    expect((await res.json()).result).toEqual([1, 1])
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events.map((e) => e.data).filter((d) => d.startsWith("message,"))).toEqual([
+      'message,raw-a,{"title":"hello"}',
+      'message,raw-b,{"title":"hello"}',
+    ])
+  }, 10000);
+
+  it("should deliver binary bodies byte for byte", async () => {
+    const stream = await sse("/subscribe/raw-binary?encoding=base64")
+    await sleep(500)
+
+    const bytes = new Uint8Array([0, 255, 1, 128])
+    const res = await publish("/publish/raw-binary", bytes, { "Content-Type": "application/octet-stream" })
+    expect((await res.json()).result).toEqual([1])
+    await sleep(500)
+    stream.close()
+
+    const message = stream.events.find((e) => e.data.startsWith("message,"))
+    expect(message!.data).toBe(`message,raw-binary,"${Buffer.from(bytes).toString("base64")}"`)
+  }, 10000);
+
+  it("should honour upstash-encoding in the response", async () => {
+    const res = await publish("/publish/raw-encoded", "x", { "upstash-encoding": "base64" })
+    expect(res.status).toBe(200)
+    expect((await res.json()).result).toEqual([0])
+  });
+
+  it("should publish to shard channels with ?sharded=true", async () => {
+    const stream = await sse("/ssubscribe/shard-a/shard-b")
+    expect(stream.status).toBe(200)
+    await sleep(500)
+
+    const res = await publish("/publish/shard-a/shard-b?sharded=true", "sharded hello")
+    expect(res.status).toBe(200)
+    expect((await res.json()).result).toEqual([1, 1])
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events.map((e) => e.data)).toEqual([
+      "ssubscribe,shard-a,1",
+      "ssubscribe,shard-b,2",
+      'smessage,shard-a,"sharded hello"',
+      'smessage,shard-b,"sharded hello"',
+    ])
+  }, 10000);
+
+  it("should not count a repeated shard channel twice", async () => {
+    const stream = await sse("/ssubscribe/shard-c/shard-c")
+    await sleep(300)
+    stream.close()
+    expect(stream.events.map((e) => e.data)).toEqual([
+      "ssubscribe,shard-c,1",
+      "ssubscribe,shard-c,1",
+    ])
+  }, 10000);
+
+  it("should not deliver sharded publishes to plain subscribers", async () => {
+    const stream = await sse("/subscribe/shard-plain")
+    await sleep(500)
+    const res = await publish("/publish/shard-plain?sharded=true", "x")
+    expect((await res.json()).result).toEqual([0])
+    stream.close()
+  }, 10000);
+});
@@ -0,0 +1,58 @@
+import { expect, it, describe } from 'bun:test';
+import { call, sleep, sse } from '../setup';
+
+const url = process.env.SR_URL
+
+/** Drop the server's subscriber connections to Redis */
+const killSubscribers = async () => {
+  const { body } = await call(["CLIENT", "KILL", "TYPE", "pubsub"])
+  expect(body.result).toBeGreaterThan(0)
+}
+
+describe("Pub/Sub Reconnection", () => {
+  it("should resubscribe and tell clients after losing Redis", async () => {
+    const stream = await sse("/subscribe/reconnect-a")
+    await sleep(500)
+    await killSubscribers()
+    // The first retry comes 250ms after the drop
+    await sleep(1500)
+    await call(["PUBLISH", "reconnect-a", "after"])
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events).toEqual([
+      { data: "subscribe,reconnect-a,1" },
+      { event: "reconnecting", data: "reconnecting" },
+      { event: "resubscribed", data: "resubscribed" },
+      { data: 'message,reconnect-a,"after"' },
+    ])
+  }, 10000);
+
+  it("should send JSON reconnection events in the JSON format", async () => {
+    const stream = await sse("/psubscribe/reconnect-p.*?format=json")
+    await sleep(500)
+    await killSubscribers()
+    await sleep(1500)
+    await call(["PUBLISH", "reconnect-p.x", "after"])
+    await sleep(500)
+    stream.close()
+
+    expect(stream.events.map((e) => [e.event, JSON.parse(e.data)])).toEqual([
+      ["psubscribe", { type: "psubscribe", pattern: "reconnect-p.*", count: 1 }],
+      ["reconnecting", { type: "reconnecting" }],
+      ["resubscribed", { type: "resubscribed" }],
+      ["pmessage", { type: "pmessage", pattern: "reconnect-p.*", channel: "reconnect-p.x", payload: "after" }],
+    ])
+  }, 10000);
+
+  it("should report the subscriber connection as healthy again", async () => {
+    const stream = await sse("/subscribe/reconnect-ready")
+    await sleep(500)
+    await killSubscribers()
+    await sleep(1500)
+    stream.close()
+
+    const ready = await (await fetch(`${url}/readyz`)).json() as any
+    expect(ready.pubsub.reconnecting).toBe(0)
+  }, 10000);
+});
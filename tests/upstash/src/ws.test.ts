@@ -0,0 +1,62 @@
+import { expect, it, describe, beforeEach } from "bun:test";
+import { cleanup, tokens } from "../setup";
+
+const url = process.env.SR_URL!.replace(/^http/, "ws") + "/ws";
+
+beforeEach(cleanup);
+
+/** Open `/ws` with `token`, collecting every JSON frame it receives */
+const connect = async (token = process.env.SR_TOKEN) => {
+  const ws = new WebSocket(url, { headers: { Authorization: `Bearer ${token}` } } as any);
+  const frames: any[] = [];
+  ws.onmessage = (e) => frames.push(JSON.parse(e.data as string));
+  await new Promise((resolve, reject) => {
+    ws.onopen = resolve;
+    ws.onerror = reject;
+  });
+  const reply = async (id: number) => {
+    for (let i = 0; i < 50 && !frames.some((f) => f.id === id); i++) {
+      await new Promise((resolve) => setTimeout(resolve, 20));
+    }
+    return frames.find((f) => f.id === id);
+  };
+  const send = (id: number, command: unknown[]) => {
+    ws.send(JSON.stringify({ id, command }));
+    return reply(id);
+  };
+  return { ws, frames, send };
+};
+
+describe("WebSocket", () => {
+  it("should run commands and echo ids", async () => {
+    const { ws, send } = await connect();
+    expect(await send(1, ["SET", "ws:key", "v"])).toEqual({ id: 1, result: "OK" });
+    expect(await send(2, ["GET", "ws:key"])).toEqual({ id: 2, result: "v" });
+    ws.close();
+  });
+
+  it("should push messages to a subscribed session", async () => {
+    const { ws, frames, send } = await connect();
+    expect((await send(1, ["SUBSCRIBE", "ws-chan"])).result).toBe(1);
+    expect((await send(2, ["PUBLISH", "ws-chan", "hello"])).result).toBe(1);
+    await new Promise((resolve) => setTimeout(resolve, 200));
+    expect(frames).toContainEqual({ type: "message", channel: "ws-chan", payload: "hello" });
+    expect((await send(3, ["UNSUBSCRIBE"])).result).toBe(0);
+    ws.close();
+  });
+
+  it("should refuse commands the token may not run", async () => {
+    const { ws, send } = await connect(tokens.readOnly);
+    const reply = await send(1, ["SET", "ws:key", "v"]);
+    expect(reply.status).toBe("forbidden");
+    expect((await send(2, ["GET", "ws:key"])).result).toBeNull();
+    ws.close();
+  });
+
+  it("should reject an upgrade without a valid token", async () => {
+    const res = await fetch(process.env.SR_URL + "/ws", {
+      headers: { Connection: "Upgrade", Upgrade: "websocket", Authorization: "Bearer wrong" },
+    });
+    expect(res.status).toBe(401);
+  });
+});